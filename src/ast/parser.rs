@@ -1,15 +1,15 @@
-use lsp_types::Range;
+use lsp_types::{Position, Range};
 use nom::Parser;
-use nom::bytes::complete::{tag, take_until};
+use nom::bytes::complete::tag;
 use nom::character::complete::multispace1;
 use nom::character::complete::{alphanumeric1, multispace0};
-use nom::combinator::{all_consuming, map, opt, recognize};
+use nom::combinator::{all_consuming, eof, map, opt, peek, recognize};
 use nom::multi::separated_list1;
-use nom::sequence::{delimited, preceded};
+use nom::sequence::{delimited, preceded, terminated};
 use nom::{IResult, branch::alt};
 use serde::Serialize;
 
-use super::primitives::{file_path, lowercase_name};
+use super::primitives::{balanced, file_path, lowercase_name};
 use super::utils::{Span, range};
 
 /// Names the entity you are talking to
@@ -18,6 +18,9 @@ use super::utils::{Span, range};
 pub struct Vocative {
     pub range: Range,
     pub name: String,
+    /// Left out of the sentence and filled in from the defaults, with an empty range
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub inferred: bool,
 }
 
 /// A simple verb template that the user can use
@@ -26,6 +29,22 @@ pub struct Vocative {
 pub struct SimpleVerb {
     pub range: Range,
     pub name: String,
+    /// Left out of the sentence and filled in from the defaults, with an empty range
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub inferred: bool,
+}
+
+/// What a verb assignment does with the template it defines, given by its operator
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssignmentMode {
+    /// `=`: saves the template unless one already exists
+    #[default]
+    Define,
+    /// `!=`: saves the template, replacing an existing one
+    Overwrite,
+    /// `:=`: uses the template for this sentence only, without saving it
+    Ephemeral,
 }
 
 /// A verb assignment that defines a new template in place
@@ -34,6 +53,7 @@ pub struct SimpleVerb {
 pub struct VerbAssignment {
     pub range: Range,
     pub name: String,
+    pub mode: AssignmentMode,
     pub value: String,
 }
 
@@ -44,6 +64,33 @@ pub enum Verb {
     Assignment(VerbAssignment),
 }
 
+impl Verb {
+    pub fn name(&self) -> &str {
+        match self {
+            Verb::Simple(node) => &node.name,
+            Verb::Assignment(node) => &node.name,
+        }
+    }
+
+    pub fn is_inferred(&self) -> bool {
+        matches!(self, Verb::Simple(node) if node.inferred)
+    }
+
+    /// The range covering only the name of the verb, without the `~` or the assigned value
+    pub fn name_range(&self) -> Range {
+        match self {
+            Verb::Simple(node) => node.range,
+            Verb::Assignment(node) => {
+                let mut range = node.range;
+                range.start.character += 1;
+                range.end = range.start;
+                range.end.character += node.name.len() as u32;
+                range
+            }
+        }
+    }
+}
+
 /// Contains free-from text
 #[derive(Debug, PartialEq, Serialize, Clone)]
 #[serde(tag = "type", rename = "freeform")]
@@ -65,9 +112,19 @@ pub struct FilePathPart {
 #[serde(tag = "type", rename = "inline_shell")]
 pub struct InlineShellPart {
     pub range: Range,
+    /// Tag selecting the interpreter, as in `$py(...)`; the default shell when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<String>,
     pub code: String,
 }
 
+/// Stands for the data piped into standard input, written as `-` or `@-`
+#[derive(Debug, PartialEq, Serialize, Clone)]
+#[serde(tag = "type", rename = "stdin")]
+pub struct StdinPart {
+    pub range: Range,
+}
+
 /// Generic parts that can contain objects or free form text
 #[derive(Debug, PartialEq, Serialize, Clone)]
 #[serde(untagged)]
@@ -75,6 +132,7 @@ pub enum Part {
     Freeform(FreeformPart),
     FilePath(FilePathPart),
     InlineShell(InlineShellPart),
+    Stdin(StdinPart),
 }
 
 /// The fully-parsed sentence. Describes a prompt.
@@ -91,6 +149,7 @@ fn vocative(input: Span) -> IResult<Span, Vocative> {
     map(lowercase_name, |name: Span| Vocative {
         range: range(name),
         name: name.to_string(),
+        inferred: false,
     })
     .parse(input)
 }
@@ -100,24 +159,28 @@ fn verb_simple(input: Span) -> IResult<Span, Verb> {
         Verb::Simple(SimpleVerb {
             range: range(name),
             name: name.to_string(),
+            inferred: false,
         })
     })
     .parse(input)
 }
 
+fn assignment_mode(input: Span) -> IResult<Span, AssignmentMode> {
+    alt((
+        map(tag("!="), |_| AssignmentMode::Overwrite),
+        map(tag(":="), |_| AssignmentMode::Ephemeral),
+        map(tag("="), |_| AssignmentMode::Define),
+    ))
+    .parse(input)
+}
+
 fn verb_assignment(input: Span) -> IResult<Span, Verb> {
     let get_parser = || {
         (
             tag("~"),
             lowercase_name,
-            preceded(
-                multispace0,
-                delimited(
-                    delimited(tag("="), multispace0, tag("(")),
-                    take_until(")"),
-                    tag(")"),
-                ),
-            ),
+            preceded(multispace0, assignment_mode),
+            delimited(preceded(multispace0, tag("(")), balanced, tag(")")),
         )
     };
 
@@ -125,10 +188,11 @@ fn verb_assignment(input: Span) -> IResult<Span, Verb> {
         .map(|result| range(result))
         .parse(input)?;
 
-    map(get_parser(), |(_, name, value)| {
+    map(get_parser(), |(_, name, mode, value)| {
         Verb::Assignment(VerbAssignment {
             range,
             name: name.to_string(),
+            mode,
             value: value.to_string(),
         })
     })
@@ -157,17 +221,31 @@ fn filepath_part(input: Span) -> IResult<Span, FilePathPart> {
 
 fn inline_shell_part(input: Span) -> IResult<Span, InlineShellPart> {
     map(
-        delimited(tag("$("), take_until(")"), tag(")")),
-        |code: Span| InlineShellPart {
+        (
+            delimited(tag("$"), opt(lowercase_name), tag("(")),
+            balanced,
+            tag(")"),
+        ),
+        |(interpreter, code, _): (Option<Span>, Span, _)| InlineShellPart {
             range: range(code),
+            interpreter: interpreter.map(|tag| tag.to_string()),
             code: code.to_string(),
         },
     )
     .parse(input)
 }
 
+fn stdin_part(input: Span) -> IResult<Span, StdinPart> {
+    map(
+        terminated(alt((tag("@-"), tag("-"))), peek(alt((multispace1, eof)))),
+        |s: Span| StdinPart { range: range(s) },
+    )
+    .parse(input)
+}
+
 fn part(input: Span) -> IResult<Span, Part> {
     alt((
+        map(stdin_part, Part::Stdin),
         map(filepath_part, Part::FilePath),
         map(freeform_part, Part::Freeform),
         map(inline_shell_part, Part::InlineShell),
@@ -186,27 +264,141 @@ fn maybe_parts(input: Span) -> IResult<Span, Vec<Part>> {
     .parse(input)
 }
 
-fn sentence(input: Span) -> IResult<Span, Sentence> {
-    let range = range(input);
+/// What a sentence may leave out. Telling a verb from a vocative takes the templates, which the
+/// parser doesn't know about.
+pub trait SentenceDefaults {
+    /// Whether `name` is the name or an alias of a verb
+    fn is_verb(&self, name: &str) -> bool;
+    /// Whether a sentence that starts with `name` starts with an abbreviated verb rather than
+    /// with a vocative
+    fn abbreviates_verb(&self, name: &str) -> bool;
+    /// Vocative of a sentence that starts with `verb`
+    fn vocative_for(&self, verb: &str) -> Option<String>;
+    /// Verb of a sentence that only has a vocative and parts
+    fn verb(&self) -> Option<String>;
+}
 
-    map(
-        delimited(
-            multispace0,
-            (vocative, multispace1, verb, maybe_parts),
-            multispace0,
-        ),
-        |(vocative, _, verb, parts)| Sentence {
-            range,
-            vocative,
-            verb,
-            parts,
-        },
-    )
-    .parse(input)
+/// Every sentence spells out its vocative and its verb
+#[cfg(test)]
+pub struct NoDefaults;
+
+#[cfg(test)]
+impl SentenceDefaults for NoDefaults {
+    fn is_verb(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn abbreviates_verb(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn vocative_for(&self, _verb: &str) -> Option<String> {
+        None
+    }
+
+    fn verb(&self) -> Option<String> {
+        None
+    }
+}
+
+fn empty_range_at(position: Position) -> Range {
+    Range::new(position, position)
+}
+
+/// Whether the sentence goes on with a word that names a known verb
+fn continues_with_verb(input: Span, defaults: &dyn SentenceDefaults) -> bool {
+    preceded(multispace1, verb_simple)
+        .parse(input)
+        .is_ok_and(|(_, verb)| defaults.is_verb(verb.name()))
 }
 
+fn sentence<'d>(
+    defaults: &'d dyn SentenceDefaults,
+) -> impl FnMut(Span) -> IResult<Span, Sentence> + 'd {
+    move |input: Span| {
+        let range = range(input);
+        let (after_first, first) = preceded(multispace0, vocative).parse(input)?;
+
+        // `review foo` stands for `<default vocative> review foo`
+        if (defaults.is_verb(&first.name) || defaults.abbreviates_verb(&first.name))
+            && !continues_with_verb(after_first, defaults)
+            && let Some(name) = defaults.vocative_for(&first.name)
+            && let Ok((rest, parts)) = terminated(maybe_parts, multispace0).parse(after_first)
+        {
+            let sentence = Sentence {
+                range,
+                vocative: Vocative {
+                    range: empty_range_at(first.range.start),
+                    name,
+                    inferred: true,
+                },
+                verb: Verb::Simple(SimpleVerb {
+                    range: first.range,
+                    name: first.name,
+                    inferred: false,
+                }),
+                parts,
+            };
+            return Ok((rest, sentence));
+        }
+
+        let explicit = preceded(multispace1, verb).parse(after_first);
+        let spelled_out = explicit.as_ref().is_ok_and(|(_, verb)| {
+            matches!(verb, Verb::Assignment(_)) || defaults.is_verb(verb.name())
+        });
+
+        // `robot foo` stands for `robot <default verb> foo` unless `foo` is a known verb. Only
+        // full names and aliases count, so `robot c the bug` doesn't turn into `create`.
+        if !spelled_out
+            && let Some(name) = defaults.verb()
+            && let Ok((rest, parts)) = terminated(maybe_parts, multispace0).parse(after_first)
+        {
+            let sentence = Sentence {
+                range,
+                verb: Verb::Simple(SimpleVerb {
+                    range: empty_range_at(first.range.end),
+                    name,
+                    inferred: true,
+                }),
+                vocative: first,
+                parts,
+            };
+            return Ok((rest, sentence));
+        }
+
+        let (rest, verb) = explicit?;
+        let (rest, parts) = terminated(maybe_parts, multispace0).parse(rest)?;
+
+        Ok((
+            rest,
+            Sentence {
+                range,
+                vocative: first,
+                verb,
+                parts,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
 pub fn parse_statement(input: Span) -> IResult<Span, Sentence> {
-    all_consuming(sentence).parse(input)
+    parse_statement_with(input, &NoDefaults)
+}
+
+/// Parses a sentence that may leave out its vocative or its verb, as far as `defaults` allow
+pub fn parse_statement_with<'a>(
+    input: Span<'a>,
+    defaults: &dyn SentenceDefaults,
+) -> IResult<Span<'a>, Sentence> {
+    all_consuming(sentence(defaults)).parse(input)
+}
+
+/// Whether `input` could be used as the name of a vocative or a verb
+pub fn is_valid_name(input: &str) -> bool {
+    all_consuming(lowercase_name)
+        .parse(Span::new(input))
+        .is_ok()
 }
 
 #[cfg(test)]
@@ -232,6 +424,10 @@ mod tests {
     #[case("qwen3 edit foo $(find . | grep hello | grep py) bar")]
     #[case("qwen3 delete bar $(git diff)")]
     #[case("qwen3 summarize $(curl https://google.com)")]
+    #[case("qwen3 explain $py(1 + 1) foo")]
+    #[case("qwen3 explain $sh(ls -a) foo")]
+    #[case("qwen3 review - foo")]
+    #[case("qwen3 review @-")]
     #[case("   whitespace allow")]
     #[case("   whitespace magic   ")]
     #[case("want some whitespace   ")]
@@ -239,6 +435,8 @@ mod tests {
     #[case("qwen3 ~foobar   =(create for me a) lorem")]
     #[case("qwen3 ~foobar=    (create for me a) lorem")]
     #[case("qwen3 ~foobar=(potato things hello) lorem")]
+    #[case("qwen3 ~foobar!=(potato things hello) lorem")]
+    #[case("qwen3 ~foobar :=(potato things hello) lorem")]
     fn parse_statement_snapshot(#[case] input: &str) {
         let mut s = insta::Settings::clone_current();
         s.set_snapshot_suffix(input.replace(' ', "_").to_string());
@@ -249,12 +447,149 @@ mod tests {
         assert_yaml_snapshot!(sentence);
     }
 
+    #[rstest]
+    #[case(
+        "qwen3 ~short:=({{ description | truncate(10) }}) lorem",
+        "{{ description | truncate(10) }}"
+    )]
+    #[case("qwen3 ~short:=(a (b (c)) d) lorem", "a (b (c)) d")]
+    fn assignments_keep_balanced_parentheses(#[case] input: &str, #[case] value: &str) {
+        let (_, sentence) = parse_statement(Span::new(input)).expect("parser should succeed");
+
+        let Verb::Assignment(assignment) = sentence.verb else {
+            panic!("expected an assignment, got {:?}", sentence.verb);
+        };
+        assert_eq!(assignment.value, value);
+    }
+
+    #[rstest]
+    #[case("qwen3 explain $py(print(1 + 1)) foo", Some("py"), "print(1 + 1)")]
+    #[case("qwen3 explain $(echo $((1 + 2))) foo", None, "echo $((1 + 2))")]
+    #[case(
+        "qwen3 explain $sh(f() { echo (a); }; f)",
+        Some("sh"),
+        "f() { echo (a); }; f"
+    )]
+    fn shell_parts_keep_balanced_parentheses(
+        #[case] input: &str,
+        #[case] interpreter: Option<&str>,
+        #[case] code: &str,
+    ) {
+        let (rest, sentence) = parse_statement(Span::new(input)).expect("parser should succeed");
+        assert_eq!(*rest.fragment(), "");
+
+        let Some(Part::InlineShell(part)) = sentence.parts.first() else {
+            panic!("expected a shell part, got {:?}", sentence.parts);
+        };
+        assert_eq!(part.interpreter.as_deref(), interpreter);
+        assert_eq!(part.code, code);
+    }
+
+    #[rstest]
+    #[case("qwen3 explain $py(print(1 + 1) foo")]
+    #[case("qwen3 explain $(echo $((1 + 2)) foo")]
+    #[case("qwen3 explain $(echo :)) foo")]
+    #[case("qwen3 explain $py(print(1)))")]
+    fn unbalanced_shell_parts_are_not_parsed(#[case] input: &str) {
+        assert!(parse_statement(Span::new(input)).is_err());
+    }
+
+    #[rstest]
+    #[case("qwen3 ~short:=(a (b) lorem")]
+    #[case("qwen3 ~short:=(smile :)) lorem")]
+    fn unbalanced_assignments_are_not_parsed(#[case] input: &str) {
+        assert!(parse_statement(Span::new(input)).is_err());
+    }
+
+    /// Knows `review`, `create` and `robotize` as verbs and `robot` as a vocative
+    struct TestDefaults {
+        vocative: Option<&'static str>,
+        verb: Option<&'static str>,
+    }
+
+    impl SentenceDefaults for TestDefaults {
+        fn is_verb(&self, name: &str) -> bool {
+            matches!(name, "review" | "create" | "robotize")
+        }
+
+        fn abbreviates_verb(&self, name: &str) -> bool {
+            name != "robot"
+                && ["review", "create", "robotize"]
+                    .iter()
+                    .any(|verb| verb.starts_with(name))
+        }
+
+        fn vocative_for(&self, _verb: &str) -> Option<String> {
+            self.vocative.map(str::to_string)
+        }
+
+        fn verb(&self) -> Option<String> {
+            self.verb.map(str::to_string)
+        }
+    }
+
+    const DEFAULTS: TestDefaults = TestDefaults {
+        vocative: Some("qwen3"),
+        verb: Some("create"),
+    };
+
+    #[rstest]
+    #[case("review foo @hello.txt")]
+    #[case("robot foo bar")]
+    #[case("robot @hello.txt")]
+    #[case("robot")]
+    fn parse_shorthand_snapshot(#[case] input: &str) {
+        let mut s = insta::Settings::clone_current();
+        s.set_snapshot_suffix(input.replace(' ', "_").to_string());
+        let _guard = s.bind_to_scope();
+        let (_, sentence) =
+            parse_statement_with(Span::new(input), &DEFAULTS).expect("parser should succeed");
+
+        assert_yaml_snapshot!(sentence);
+    }
+
+    #[rstest]
+    #[case("robot review foo", "robot", "review")]
+    #[case("review create", "review", "create")]
+    #[case("robot ~foo=(bar) baz", "robot", "foo")]
+    #[case("create foo", "qwen3", "create")]
+    #[case("robot foo", "robot", "create")]
+    #[case("rev foo", "qwen3", "rev")]
+    #[case("robot c the bug", "robot", "create")]
+    #[case("robot rev foo", "robot", "create")]
+    fn shorthand_only_applies_to_unknown_words(
+        #[case] input: &str,
+        #[case] vocative: &str,
+        #[case] verb: &str,
+    ) {
+        let (_, sentence) = parse_statement_with(Span::new(input), &DEFAULTS).unwrap();
+
+        assert_eq!(sentence.vocative.name, vocative);
+        assert_eq!(sentence.verb.name(), verb);
+    }
+
+    #[test]
+    fn shorthand_needs_defaults() {
+        let no_defaults = TestDefaults {
+            vocative: None,
+            verb: None,
+        };
+
+        let (_, sentence) = parse_statement_with(Span::new("create foo"), &no_defaults).unwrap();
+        assert_eq!(sentence.vocative.name, "create");
+        assert!(!sentence.vocative.inferred);
+        assert!(parse_statement_with(Span::new("robot @hello.txt"), &no_defaults).is_err());
+    }
+
     #[rstest]
     #[case("42run")]
     #[case("john run!")]
     #[case(" run")]
     #[case("")]
     #[case("alice! jump")]
+    #[case("qwen3 explain $Py(ls)")]
+    #[case("qwen3 review -foo")]
+    #[case("qwen3 ~foobar=!(lorem)")]
     fn test_parse_statement_failure(#[case] input: &str) {
         assert!(parse_statement(Span::new(input)).is_err());
     }
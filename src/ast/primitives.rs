@@ -3,8 +3,9 @@ use nom::bytes::complete::take_while1;
 use nom::character::complete::char;
 use nom::character::complete::one_of;
 use nom::combinator::recognize;
+use nom::error::{Error, ErrorKind};
 use nom::multi::many1;
-use nom::{IResult, branch::alt};
+use nom::{IResult, Input, branch::alt};
 
 use super::utils::Span;
 
@@ -39,3 +40,19 @@ pub fn lowercase_name(input: Span) -> IResult<Span, Span> {
 pub fn file_path(input: Span) -> IResult<Span, Span> {
     take_while1(|c: char| !c.is_whitespace()).parse(input)
 }
+
+/// Text up to the `)` that closes an opening `(` in front of it, so that the text itself may
+/// contain balanced parentheses
+pub fn balanced(input: Span) -> IResult<Span, Span> {
+    let mut depth = 0usize;
+    for (i, c) in input.fragment().char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Ok(input.take_split(i)),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Err(nom::Err::Error(Error::new(input, ErrorKind::TakeUntil)))
+}
@@ -4,27 +4,26 @@ use nom_locate::LocatedSpan;
 pub type Span<'a> = LocatedSpan<&'a str>;
 
 pub fn range(span: Span) -> Range {
-    let start_offset = span.location_offset();
-    let start_line = span.location_line();
+    let start_line = span.location_line() - 1;
+    let start_character = span.get_column() as u32 - 1;
+    let fragment = span.fragment();
 
-    let mut end_offset = start_offset;
-    let mut end_line = start_line;
-
-    for ch in span.fragment().chars() {
-        end_offset += ch.len_utf8();
-        if ch == '\n' {
-            end_line += 1;
-        }
-    }
+    let (end_line, end_character) = match fragment.rfind('\n') {
+        Some(last_newline) => (
+            start_line + fragment.matches('\n').count() as u32,
+            (fragment.len() - last_newline - 1) as u32,
+        ),
+        None => (start_line, start_character + fragment.len() as u32),
+    };
 
     Range {
         start: Position {
-            line: start_line - 1,
-            character: start_offset as u32,
+            line: start_line,
+            character: start_character,
         },
         end: Position {
-            line: end_line - 1,
-            character: end_offset as u32,
+            line: end_line,
+            character: end_character,
         },
     }
 }
@@ -0,0 +1,937 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+use std::time::Duration;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::engine::PromptInputs;
+use crate::error::LakonikError;
+use crate::shell::{Isolation, OnFailure, ShellPolicy};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Directory that holds the project `config.toml` and the project templates
+const PROJECT_DIR: &str = ".lakonik";
+
+/// Prefix of the environment variables that override settings, e.g. `LAKONIK_SHELL_TIMEOUT`
+const ENV_PREFIX: &str = "LAKONIK_";
+
+/// Where a setting comes from, from lowest to highest precedence. Command-line flags are applied
+/// on top by each command.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ConfigLayer {
+    Default,
+    User,
+    /// Can only tighten the user's settings, see [`tighten`]
+    Project,
+    Env,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigLayer::Default => "default",
+            ConfigLayer::User => "user",
+            ConfigLayer::Project => "project",
+            ConfigLayer::Env => "env",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Vocative of sentences that start with a verb
+    pub default_vocative: Option<String>,
+    /// Verb of sentences that only name a vocative and parts
+    pub default_verb: Option<String>,
+    /// TOML file with an API key per provider, `keys.toml` next to the user `config.toml` by
+    /// default
+    pub keyring: Option<PathBuf>,
+    pub providers: BTreeMap<String, ProviderSettings>,
+    pub shell: ShellSettings,
+    pub attachments: AttachmentSettings,
+    pub cache: CacheSettings,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderSettings {
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    /// Variable holding the API key, `<NAME>_API_KEY` by default
+    pub api_key_env: Option<String>,
+}
+
+/// The shell policy of `eval`, as set by the `--shell-*` flags
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShellSettings {
+    /// When set, every command has to match one of these entries
+    pub allow: Option<Vec<String>>,
+    /// Commands that may never run, on a best-effort basis: the sandbox and `allow` are what
+    /// actually keep commands out
+    pub deny: Vec<String>,
+    /// Seconds a command may run
+    pub timeout: u64,
+    pub jobs: usize,
+    /// Bytes a command may output
+    pub max_output: usize,
+    pub working_dir: Option<PathBuf>,
+    pub minimal_env: bool,
+    pub sandbox: bool,
+    pub read_only: bool,
+    pub network: bool,
+    pub confirm: bool,
+    pub on_failure: OnFailure,
+    /// Added to the built-in interpreters, keyed by tag
+    pub interpreters: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for ShellSettings {
+    fn default() -> Self {
+        let policy = ShellPolicy::default();
+
+        Self {
+            allow: policy.allow,
+            deny: policy.deny,
+            timeout: policy.timeout.as_secs(),
+            jobs: policy.max_parallel,
+            max_output: policy.max_output_bytes,
+            working_dir: policy.working_dir,
+            minimal_env: policy.minimal_env,
+            sandbox: false,
+            read_only: false,
+            network: true,
+            confirm: policy.confirm,
+            on_failure: policy.on_failure,
+            interpreters: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<&ShellSettings> for ShellPolicy {
+    fn from(settings: &ShellSettings) -> Self {
+        let mut interpreters = ShellPolicy::default().interpreters;
+        interpreters.extend(settings.interpreters.clone());
+
+        ShellPolicy {
+            allow: settings.allow.clone(),
+            deny: settings.deny.clone(),
+            deny_options: Vec::new(),
+            timeout: Duration::from_secs(settings.timeout),
+            max_output_bytes: settings.max_output,
+            max_parallel: settings.jobs,
+            working_dir: settings.working_dir.clone(),
+            minimal_env: settings.minimal_env,
+            isolation: if settings.sandbox {
+                Isolation::Sandbox {
+                    read_only: settings.read_only,
+                    network: settings.network,
+                }
+            } else {
+                Isolation::None
+            },
+            confirm: settings.confirm,
+            on_failure: settings.on_failure,
+            interpreters,
+            stubs: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentSettings {
+    /// Combined size that file attachments and piped input may not exceed
+    pub max_bytes: u64,
+}
+
+impl Default for AttachmentSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: PromptInputs::default().max_attachment_bytes,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    /// Let the language server reuse the output of previewed shell code until a document is
+    /// saved
+    pub shell_previews: bool,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            shell_previews: true,
+        }
+    }
+}
+
+/// Where an API key was found
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum KeySource {
+    Env(String),
+    Keyring(PathBuf),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Env(name) => write!(f, "${name}"),
+            KeySource::Keyring(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Settings {
+    /// The API key of a provider, from its environment variable or else from the keyring file
+    pub fn api_key(&self, provider: &str) -> Result<Option<(String, KeySource)>, LakonikError> {
+        let variable = self
+            .providers
+            .get(provider)
+            .and_then(|p| p.api_key_env.clone())
+            .unwrap_or_else(|| format!("{}_API_KEY", provider.to_uppercase().replace('-', "_")));
+        if let Ok(key) = std::env::var(&variable) {
+            return Ok(Some((key, KeySource::Env(variable))));
+        }
+
+        let Some(keyring) = self
+            .keyring
+            .clone()
+            .or_else(|| Some(user_config_file()?.with_file_name("keys.toml")))
+        else {
+            return Ok(None);
+        };
+        let keys = read_table(&keyring)?;
+
+        Ok(keys
+            .get(provider)
+            .and_then(Value::as_str)
+            .map(|key| (key.to_string(), KeySource::Keyring(keyring))))
+    }
+}
+
+/// The user `config.toml`: `LAKONIK_CONFIG_FILE` if set, or else the one in the user
+/// configuration directory
+pub fn user_config_file() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("LAKONIK_CONFIG_FILE") {
+        return Some(PathBuf::from(path));
+    }
+
+    ProjectDirs::from("", "", "lakonik").map(|pd| pd.config_dir().join(CONFIG_FILE))
+}
+
+/// Where the search for the `.lakonik` directory starts in place of the current directory. The
+/// language server sets it to its workspace, since editors rarely start it from there.
+static PROJECT_ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn set_project_root(root: Option<PathBuf>) {
+    *PROJECT_ROOT.write().unwrap_or_else(PoisonError::into_inner) = root;
+}
+
+/// The directory project settings and templates are looked up from
+pub fn project_root() -> Option<PathBuf> {
+    PROJECT_ROOT
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .or_else(|| std::env::current_dir().ok())
+}
+
+/// The `config.toml` of the closest `.lakonik` directory, starting from `start`
+pub fn find_project_config_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|dir| dir.is_dir())
+        .map(|dir| dir.join(CONFIG_FILE))
+}
+
+/// The file that `lakonik config set` writes to, which may not exist yet
+pub fn layer_file(layer: ConfigLayer) -> Result<PathBuf, LakonikError> {
+    let file = match layer {
+        ConfigLayer::User => user_config_file(),
+        ConfigLayer::Project => project_root().map(|root| {
+            find_project_config_file(&root)
+                .unwrap_or_else(|| root.join(PROJECT_DIR).join(CONFIG_FILE))
+        }),
+        ConfigLayer::Default | ConfigLayer::Env => None,
+    };
+
+    file.ok_or_else(|| LakonikError::Config {
+        message: format!("there is no {layer} configuration file"),
+    })
+}
+
+/// A missing file is an empty table
+fn read_table(path: &Path) -> Result<Table, LakonikError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(source) => {
+            return Err(LakonikError::Io {
+                path: path.to_path_buf(),
+                source,
+            });
+        }
+    };
+
+    contents.parse().map_err(|err| LakonikError::Config {
+        message: format!("invalid configuration in `{}`: {err}", path.display()),
+    })
+}
+
+fn into_settings(table: Table, origin: &str) -> Result<Settings, LakonikError> {
+    Value::Table(table)
+        .try_into()
+        .map_err(|err| LakonikError::Config {
+            message: format!("invalid configuration in {origin}: {err}"),
+        })
+}
+
+/// A value as written in TOML, or else the raw text as a string, so that `set shell.timeout 10`
+/// stores a number and `set default_vocative qwen3` a string
+fn parse_value(raw: &str) -> Value {
+    format!("value = {raw}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let (head, rest) = match key.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (key, None),
+    };
+    let value = table.get(head)?;
+
+    match rest {
+        Some(rest) => lookup(value.as_table()?, rest),
+        None => Some(value),
+    }
+}
+
+/// Whether `key` names a setting, set or not. The settings are offered a placeholder for it,
+/// which they reject for its type if they know the key, and as unknown otherwise.
+fn is_known_key(key: &str) -> bool {
+    let mut table = Table::new();
+    if insert(&mut table, key, Value::Table(Table::new())).is_err() {
+        return false;
+    }
+
+    match Value::Table(table).try_into::<Settings>() {
+        Ok(_) => true,
+        Err(err) => !err.to_string().contains("unknown field"),
+    }
+}
+
+fn insert(table: &mut Table, key: &str, value: Value) -> Result<(), LakonikError> {
+    let Some((head, rest)) = key.split_once('.') else {
+        table.insert(key.to_string(), value);
+        return Ok(());
+    };
+
+    match table
+        .entry(head.to_string())
+        .or_insert_with(|| Value::Table(Table::new()))
+    {
+        Value::Table(inner) => insert(inner, rest, value),
+        _ => Err(LakonikError::Config {
+            message: format!("`{head}` is not a table"),
+        }),
+    }
+}
+
+/// Tables are merged key by key, anything else in `overlay` replaces what is in `base`
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Settings that a project can't change at all, since its `config.toml` comes with the
+/// repository rather than from the user
+const USER_ONLY_KEYS: &[&str] = &[
+    "keyring",
+    "providers",
+    "shell.interpreters",
+    "shell.working_dir",
+];
+
+fn check_project_keys(table: &Table) -> Result<(), LakonikError> {
+    match USER_ONLY_KEYS
+        .iter()
+        .find(|key| lookup(table, key).is_some())
+    {
+        Some(key) => Err(LakonikError::Config {
+            message: format!("`{key}` can't be set by the project configuration"),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Turns a project layer into one that can only tighten `base`: deny lists are added to,
+/// allowlists intersected, limits lowered and safeguards turned on. Values that would loosen
+/// the policy are dropped.
+fn tighten(base: &Settings, mut project: Table) -> Result<Table, LakonikError> {
+    check_project_keys(&project)?;
+
+    if let Some(Value::Table(shell)) = project.get_mut("shell") {
+        let base = &base.shell;
+        let strings = |value: &Value| -> Vec<String> {
+            value
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect()
+        };
+
+        if let Some(deny) = shell.get("deny").map(strings) {
+            let mut merged = base.deny.clone();
+            merged.extend(deny.into_iter().filter(|entry| !base.deny.contains(entry)));
+            shell.insert("deny".to_string(), merged.into());
+        }
+        if let (Some(allow), Some(base_allow)) = (shell.get("allow").map(strings), &base.allow) {
+            let intersection = allow
+                .into_iter()
+                .filter(|entry| base_allow.contains(entry))
+                .collect::<Vec<_>>();
+            shell.insert("allow".to_string(), intersection.into());
+        }
+        for (key, strict) in [
+            ("sandbox", true),
+            ("read_only", true),
+            ("minimal_env", true),
+            ("confirm", true),
+            ("network", false),
+        ] {
+            if shell.get(key).and_then(Value::as_bool) == Some(!strict) {
+                shell.remove(key);
+            }
+        }
+        for (key, limit) in [
+            ("timeout", base.timeout),
+            ("jobs", base.jobs as u64),
+            ("max_output", base.max_output as u64),
+        ] {
+            if shell
+                .get(key)
+                .and_then(Value::as_integer)
+                .is_some_and(|value| value as u64 > limit)
+            {
+                shell.remove(key);
+            }
+        }
+        if shell
+            .get("on_failure")
+            .and_then(|value| value.clone().try_into::<OnFailure>().ok())
+            .is_some_and(|on_failure| on_failure > base.on_failure)
+        {
+            shell.remove("on_failure");
+        }
+    }
+
+    if let Some(Value::Table(attachments)) = project.get_mut("attachments")
+        && attachments
+            .get("max_bytes")
+            .and_then(Value::as_integer)
+            .is_some_and(|value| value as u64 > base.attachments.max_bytes)
+    {
+        attachments.remove("max_bytes");
+    }
+
+    Ok(project)
+}
+
+/// Every value that is not a table, keyed by its dotted path
+fn flatten(prefix: &str, table: &Table, out: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Table(inner) => flatten(&path, inner, out),
+            value => out.push((path, value.clone())),
+        }
+    }
+}
+
+/// Dotted paths of the settings that have a fixed name, including the ones that are unset by
+/// default
+fn known_keys() -> Vec<String> {
+    fn walk(prefix: &str, value: &serde_json::Value, out: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) if !map.is_empty() => {
+                for (key, value) in map {
+                    let path = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    walk(&path, value, out);
+                }
+            }
+            serde_json::Value::Object(_) => {}
+            _ => out.push(prefix.to_string()),
+        }
+    }
+
+    let defaults = serde_json::to_value(Settings::default()).expect("settings serialize to JSON");
+    let mut keys = Vec::new();
+    walk("", &defaults, &mut keys);
+
+    keys
+}
+
+fn env_var_name(key: &str) -> String {
+    format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Settings overridden by `LAKONIK_*` environment variables
+fn env_table() -> Table {
+    let mut table = Table::new();
+    for key in known_keys() {
+        if let Ok(raw) = std::env::var(env_var_name(&key)) {
+            // Known keys never run into a non-table value
+            let _ = insert(&mut table, &key, parse_value(&raw));
+        }
+    }
+
+    table
+}
+
+/// The merged settings along with the layers they were merged from
+#[derive(Debug)]
+pub struct Config {
+    layers: Vec<(ConfigLayer, Table)>,
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Reads the user and project `config.toml` and the environment
+    pub fn load() -> Result<Self, LakonikError> {
+        let mut layers = Vec::new();
+        if let Some(file) = user_config_file() {
+            layers.push((ConfigLayer::User, read_table(&file)?));
+        }
+        let root = project_root().ok_or_else(|| LakonikError::Config {
+            message: "the current directory is not accessible".to_string(),
+        })?;
+        if let Some(file) = find_project_config_file(&root) {
+            layers.push((ConfigLayer::Project, read_table(&file)?));
+        }
+        layers.push((ConfigLayer::Env, env_table()));
+
+        Self::from_layers(layers)
+    }
+
+    fn from_layers(layers: Vec<(ConfigLayer, Table)>) -> Result<Self, LakonikError> {
+        let mut merged = Table::new();
+        let mut layers = layers;
+        for (layer, table) in &mut layers {
+            // Check each layer on its own, so that errors point at the file that has them
+            into_settings(table.clone(), &format!("the {layer} layer"))?;
+            if *layer == ConfigLayer::Project {
+                let base = into_settings(merged.clone(), "the user configuration")?;
+                *table = tighten(&base, std::mem::take(table))?;
+            }
+            merge(&mut merged, table.clone());
+        }
+        let settings = into_settings(merged, "the merged configuration")?;
+
+        Ok(Self { layers, settings })
+    }
+
+    fn effective(&self) -> Table {
+        match Value::try_from(&self.settings) {
+            Ok(Value::Table(table)) => table,
+            _ => Table::new(),
+        }
+    }
+
+    /// The highest layer that sets `key`
+    fn origin(&self, key: &str) -> ConfigLayer {
+        self.layers
+            .iter()
+            .rev()
+            .find(|(_, table)| lookup(table, key).is_some())
+            .map_or(ConfigLayer::Default, |(layer, _)| *layer)
+    }
+
+    /// The value of a dotted key such as `shell.timeout`, or `None` if it is unset. Keys that
+    /// name no setting are an error.
+    pub fn get(&self, key: &str) -> Result<Option<Value>, LakonikError> {
+        if !is_known_key(key) {
+            return Err(LakonikError::Config {
+                message: format!("`{key}` is not a setting"),
+            });
+        }
+
+        Ok(lookup(&self.effective(), key).cloned())
+    }
+
+    /// Every setting that has a value, with the layer it comes from
+    pub fn entries(&self) -> Vec<(String, Value, ConfigLayer)> {
+        let mut values = Vec::new();
+        flatten("", &self.effective(), &mut values);
+
+        values
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = self.origin(&key);
+                (key, value, origin)
+            })
+            .collect()
+    }
+}
+
+/// Sets a dotted key in the `config.toml` of a layer, rejecting keys and values that the settings
+/// don't accept
+pub fn set(layer: ConfigLayer, key: &str, raw: &str) -> Result<PathBuf, LakonikError> {
+    let path = layer_file(layer)?;
+    set_in(&path, layer, key, raw)?;
+
+    Ok(path)
+}
+
+fn set_in(path: &Path, layer: ConfigLayer, key: &str, raw: &str) -> Result<(), LakonikError> {
+    let value = parse_value(raw);
+    let mut table = read_table(path)?;
+    insert(&mut table, key, value.clone())?;
+    into_settings(table.clone(), &format!("`{}`", path.display()))?;
+    if layer == ConfigLayer::Project {
+        check_project_keys(&table)?;
+    }
+
+    let io_error = |source| LakonikError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let invalid = |err: &dyn fmt::Display| LakonikError::Config {
+        message: format!("could not write `{}`: {err}", path.display()),
+    };
+    // The file is edited rather than rewritten, so that its comments and order stay as they are
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(source) => return Err(io_error(source)),
+    };
+    let mut document = contents
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|err| invalid(&err))?;
+    let mut value = value
+        .to_string()
+        .parse::<toml_edit::Value>()
+        .map_err(|err| invalid(&err))?;
+    value.decor_mut().clear();
+    insert_item(document.as_table_mut(), key, value)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    fs::write(path, document.to_string()).map_err(io_error)
+}
+
+/// Like [`insert`], for a document that is edited in place
+fn insert_item(
+    table: &mut dyn toml_edit::TableLike,
+    key: &str,
+    value: toml_edit::Value,
+) -> Result<(), LakonikError> {
+    let Some((head, rest)) = key.split_once('.') else {
+        // Replacing the item rather than the entry keeps the comments above the key
+        match table.get_mut(key) {
+            Some(item) => *item = toml_edit::Item::Value(value),
+            None => {
+                table.insert(key, toml_edit::Item::Value(value));
+            }
+        }
+        return Ok(());
+    };
+    if table.get(head).is_none() {
+        let mut inner = toml_edit::Table::new();
+        inner.set_implicit(true);
+        table.insert(head, toml_edit::Item::Table(inner));
+    }
+
+    match table
+        .get_mut(head)
+        .and_then(toml_edit::Item::as_table_like_mut)
+    {
+        Some(inner) => insert_item(inner, rest, value),
+        None => Err(LakonikError::Config {
+            message: format!("`{head}` is not a table"),
+        }),
+    }
+}
+
+/// How a value is shown by `lakonik config get` and `list`: strings as they are, anything else
+/// as TOML
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn table(source: &str) -> Table {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let config = Config::from_layers(vec![
+            (
+                ConfigLayer::User,
+                table("default_vocative = \"qwen3\"\n[shell]\ntimeout = 10\ndeny = [\"rm\"]"),
+            ),
+            (ConfigLayer::Project, table("[shell]\ntimeout = 5")),
+            (ConfigLayer::Env, table("[attachments]\nmax_bytes = 1024")),
+        ])
+        .unwrap();
+
+        assert_eq!(config.settings.default_vocative.as_deref(), Some("qwen3"));
+        assert_eq!(config.settings.shell.timeout, 5);
+        assert_eq!(config.settings.shell.deny, vec!["rm".to_string()]);
+        assert_eq!(config.settings.attachments.max_bytes, 1024);
+        assert_eq!(
+            config.get("shell.timeout").unwrap(),
+            Some(Value::Integer(5))
+        );
+        assert_eq!(config.origin("shell.timeout"), ConfigLayer::Project);
+        assert_eq!(config.origin("shell.deny"), ConfigLayer::User);
+        assert_eq!(config.origin("shell.jobs"), ConfigLayer::Default);
+        assert!(config.entries().contains(&(
+            "shell.jobs".to_string(),
+            Value::Integer(4),
+            ConfigLayer::Default
+        )));
+    }
+
+    #[test]
+    fn projects_can_only_tighten_the_user_policy() {
+        let user = table(
+            "[shell]\nsandbox = true\nconfirm = true\ntimeout = 10\n\
+             deny = [\"rm\"]\nallow = [\"ls\", \"git\"]",
+        );
+        let config = Config::from_layers(vec![
+            (ConfigLayer::User, user.clone()),
+            (
+                ConfigLayer::Project,
+                table(
+                    "[shell]\nsandbox = false\nconfirm = false\nnetwork = false\n\
+                     timeout = 600\ndeny = []\nallow = [\"ls\", \"curl\"]",
+                ),
+            ),
+        ])
+        .unwrap();
+
+        let shell = &config.settings.shell;
+        assert!(shell.sandbox);
+        assert!(shell.confirm);
+        assert!(!shell.network);
+        assert_eq!(shell.timeout, 10);
+        assert_eq!(shell.deny, vec!["rm".to_string()]);
+        assert_eq!(shell.allow, Some(vec!["ls".to_string()]));
+        assert_eq!(config.origin("shell.sandbox"), ConfigLayer::User);
+
+        let extended = Config::from_layers(vec![
+            (ConfigLayer::User, user.clone()),
+            (ConfigLayer::Project, table("[shell]\ndeny = [\"curl\"]")),
+        ])
+        .unwrap();
+        assert_eq!(
+            extended.settings.shell.deny,
+            vec!["rm".to_string(), "curl".to_string()]
+        );
+
+        for key in [
+            "keyring = \"/tmp/keys.toml\"",
+            "[providers.openai]\nbase_url = \"http://localhost\"",
+            "[shell.interpreters]\nsh = [\"sh\", \"-c\"]",
+            "[shell]\nworking_dir = \"/\"",
+        ] {
+            let loosened = Config::from_layers(vec![
+                (ConfigLayer::User, user.clone()),
+                (ConfigLayer::Project, table(key)),
+            ]);
+            assert!(loosened.unwrap_err().to_string().contains("project"));
+        }
+    }
+
+    #[rstest]
+    #[case(16, 2)]
+    #[case(1, 1)]
+    fn projects_can_only_lower_jobs(#[case] project: i64, #[case] expected: usize) {
+        let config = Config::from_layers(vec![
+            (ConfigLayer::User, table("[shell]\njobs = 2")),
+            (
+                ConfigLayer::Project,
+                table(&format!("[shell]\njobs = {project}")),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(config.settings.shell.jobs, expected);
+    }
+
+    #[rstest]
+    #[case("omit", OnFailure::Warn)]
+    #[case("warn", OnFailure::Warn)]
+    #[case("abort", OnFailure::Abort)]
+    fn projects_can_only_make_failures_stricter(
+        #[case] project: &str,
+        #[case] expected: OnFailure,
+    ) {
+        let config = Config::from_layers(vec![
+            (ConfigLayer::User, table("[shell]\non_failure = \"warn\"")),
+            (
+                ConfigLayer::Project,
+                table(&format!("[shell]\non_failure = \"{project}\"")),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(config.settings.shell.on_failure, expected);
+    }
+
+    #[test]
+    fn unknown_keys_and_wrong_types_are_rejected() {
+        let unknown = Config::from_layers(vec![(ConfigLayer::User, table("[shell]\ntimout = 5"))]);
+        assert!(unknown.unwrap_err().to_string().contains("user layer"));
+
+        let wrong_type = Config::from_layers(vec![(
+            ConfigLayer::Project,
+            table("[shell]\ntimeout = \"soon\""),
+        )]);
+        assert!(wrong_type.is_err());
+    }
+
+    #[test]
+    fn values_are_parsed_as_toml_or_kept_as_strings() {
+        assert_eq!(parse_value("10"), Value::Integer(10));
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(
+            parse_value("[\"git\", \"ls\"]"),
+            Value::Array(vec!["git".into(), "ls".into()])
+        );
+        assert_eq!(parse_value("qwen3"), Value::String("qwen3".to_string()));
+    }
+
+    #[test]
+    fn every_fixed_setting_has_an_environment_variable() {
+        let keys = known_keys();
+
+        assert!(keys.contains(&"default_vocative".to_string()));
+        assert!(keys.contains(&"shell.max_output".to_string()));
+        assert_eq!(env_var_name("shell.max_output"), "LAKONIK_SHELL_MAX_OUTPUT");
+        assert!(!keys.iter().any(|k| k.starts_with("providers")));
+    }
+
+    #[test]
+    fn set_writes_the_layer_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.toml");
+
+        set_in(&file, ConfigLayer::User, "shell.timeout", "12").unwrap();
+        set_in(&file, ConfigLayer::User, "providers.openai.model", "gpt-4o").unwrap();
+        assert!(set_in(&file, ConfigLayer::User, "shell.timeout", "soon").is_err());
+        assert!(set_in(&file, ConfigLayer::User, "shell.nope", "1").is_err());
+
+        let written = read_table(&file).unwrap();
+        assert_eq!(lookup(&written, "shell.timeout"), Some(&Value::Integer(12)));
+        assert_eq!(
+            lookup(&written, "providers.openai.model"),
+            Some(&Value::String("gpt-4o".to_string()))
+        );
+    }
+
+    #[test]
+    fn set_keeps_comments_and_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.toml");
+        let before = "# Who to ask\ndefault_vocative = \"qwen3\"\n\n\
+                      [shell]\n# Slow machine\ntimeout = 60\njobs = 2\n";
+        fs::write(&file, before).unwrap();
+
+        set_in(&file, ConfigLayer::User, "shell.timeout", "90").unwrap();
+        set_in(&file, ConfigLayer::User, "attachments.max_bytes", "1024").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "# Who to ask\ndefault_vocative = \"qwen3\"\n\n\
+             [shell]\n# Slow machine\ntimeout = 90\njobs = 2\n\n\
+             [attachments]\nmax_bytes = 1024\n"
+        );
+    }
+
+    #[rstest]
+    #[case("shell.timeout", true)]
+    #[case("default_vocative", true)]
+    #[case("shell.allow", true)]
+    #[case("providers.openai.model", true)]
+    #[case("shell.nope", false)]
+    #[case("nope", false)]
+    #[case("providers.openai.nope", false)]
+    fn only_settings_are_known_keys(#[case] key: &str, #[case] known: bool) {
+        assert_eq!(is_known_key(key), known);
+    }
+
+    #[test]
+    fn api_keys_come_from_the_environment_or_the_keyring() {
+        let dir = tempfile::tempdir().unwrap();
+        let keyring = dir.path().join("keys.toml");
+        fs::write(&keyring, "testprovider = \"from-keyring\"").unwrap();
+        let mut settings = Settings {
+            keyring: Some(keyring.clone()),
+            ..Settings::default()
+        };
+
+        assert_eq!(
+            settings.api_key("testprovider").unwrap(),
+            Some(("from-keyring".to_string(), KeySource::Keyring(keyring)))
+        );
+
+        unsafe {
+            std::env::set_var("LAKONIK_TEST_PROVIDER_KEY", "from-env");
+        }
+        settings.providers.insert(
+            "testprovider".to_string(),
+            ProviderSettings {
+                api_key_env: Some("LAKONIK_TEST_PROVIDER_KEY".to_string()),
+                ..ProviderSettings::default()
+            },
+        );
+        assert_eq!(
+            settings.api_key("testprovider").unwrap(),
+            Some((
+                "from-env".to_string(),
+                KeySource::Env("LAKONIK_TEST_PROVIDER_KEY".to_string())
+            ))
+        );
+        assert_eq!(settings.api_key("otherprovider").unwrap(), None);
+    }
+}
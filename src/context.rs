@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::Part, engine::ShellResult, hir::sentence::AnalyzedSentence, templates::metadata::PartKind,
+};
+
+/// Everything a verb template receives when it is rendered. Besides `{{ description }}`, a
+/// template can lay out the sentence itself:
+///
+/// ```text
+/// {{ description }}
+/// {% for file in attachments %}
+/// --- {{ file.path }} ---
+/// {{ file.contents }}
+/// {% endfor %}
+/// {% if git_branch %}We are on the `{{ git_branch }}` branch.{% endif %}
+/// ```
+#[derive(Debug, Default, Serialize)]
+pub struct RenderContext {
+    /// The parts of the sentence rendered and joined with spaces
+    pub description: String,
+    /// Name of the vocative the sentence is addressed to
+    pub vocative: String,
+    /// Name of the verb as written in the sentence
+    pub verb: String,
+    /// Every part of the sentence, in order
+    pub parts: Vec<PartContext>,
+    /// Attached files with their contents
+    pub attachments: Vec<FileContext>,
+    /// Results of the inline code, in order
+    pub shell: Vec<ShellContext>,
+    /// Free-form words that the verb template lists as its modifiers
+    pub modifiers: Vec<String>,
+    /// Directory the prompt is evaluated in
+    pub cwd: String,
+    /// Branch checked out in `cwd`, if it is in a git repository and `HEAD` is not detached
+    pub git_branch: Option<String>,
+    /// Local date as `YYYY-MM-DD`
+    pub date: String,
+    /// Operating system, such as `linux` or `macos`
+    pub os: String,
+}
+
+/// What a fixture fixes in place of the machine it runs on and the day it runs, so that its
+/// prompts render the same everywhere
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct FixtureContext {
+    pub cwd: String,
+    pub git_branch: Option<String>,
+    pub date: String,
+    pub os: String,
+    /// Contents of the files that `read_file(path)` may read, keyed by their path. Nothing is
+    /// read from disk.
+    pub files: BTreeMap<String, String>,
+}
+
+impl Default for FixtureContext {
+    fn default() -> Self {
+        Self {
+            cwd: "/project".to_string(),
+            git_branch: Some("main".to_string()),
+            date: "2000-01-01".to_string(),
+            os: "linux".to_string(),
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartContext {
+    pub kind: PartKind,
+    /// The word, path or code as written; empty for piped input
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileContext {
+    pub path: String,
+    /// Missing when the file can't be read
+    pub contents: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShellContext {
+    pub interpreter: String,
+    pub code: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the command was killed
+    pub exit_code: Option<i32>,
+    /// The signal that killed the command, if known
+    pub signal: Option<i32>,
+}
+
+impl RenderContext {
+    /// Names of the variables a verb template receives
+    pub fn variables() -> Vec<String> {
+        match serde_json::to_value(RenderContext::default()) {
+            Ok(serde_json::Value::Object(fields)) => fields.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn new(
+        sentence: &AnalyzedSentence,
+        description: String,
+        shell_results: &[ShellResult],
+        fixture: Option<&FixtureContext>,
+    ) -> Self {
+        let parts = &sentence.node.parts;
+        let declared_modifiers = sentence
+            .verb
+            .template
+            .as_ref()
+            .map(|t| t.metadata.modifiers.as_slice())
+            .unwrap_or_default();
+        let cwd = std::env::current_dir().unwrap_or_default();
+
+        RenderContext {
+            description,
+            vocative: sentence.vocative.name.clone(),
+            verb: sentence.verb.name.clone(),
+            parts: parts.iter().map(PartContext::new).collect(),
+            attachments: parts
+                .iter()
+                .filter_map(|part| match part {
+                    Part::FilePath(part) => Some(FileContext {
+                        path: part.path.clone(),
+                        contents: fs::read(&part.path)
+                            .ok()
+                            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+                    }),
+                    _ => None,
+                })
+                .collect(),
+            shell: shell_results
+                .iter()
+                .map(|result| ShellContext {
+                    interpreter: result.interpreter.clone(),
+                    code: result.code.clone(),
+                    stdout: result.output.stdout.clone(),
+                    stderr: result.output.stderr.clone(),
+                    exit_code: result.output.exit_code,
+                    signal: result.output.signal,
+                })
+                .collect(),
+            modifiers: parts
+                .iter()
+                .filter_map(|part| match part {
+                    Part::Freeform(part) if declared_modifiers.contains(&part.text) => {
+                        Some(part.text.clone())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            git_branch: git_branch(&cwd),
+            cwd: cwd.to_string_lossy().into_owned(),
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            os: std::env::consts::OS.to_string(),
+        }
+        .pinned_by(fixture)
+    }
+
+    fn pinned_by(self, fixture: Option<&FixtureContext>) -> Self {
+        let Some(fixture) = fixture else {
+            return self;
+        };
+
+        RenderContext {
+            cwd: fixture.cwd.clone(),
+            git_branch: fixture.git_branch.clone(),
+            date: fixture.date.clone(),
+            os: fixture.os.clone(),
+            ..self
+        }
+    }
+}
+
+impl PartContext {
+    fn new(part: &Part) -> Self {
+        let text = match part {
+            Part::Freeform(part) => part.text.clone(),
+            Part::FilePath(part) => part.path.clone(),
+            Part::InlineShell(part) => part.code.clone(),
+            Part::Stdin(_) => String::new(),
+        };
+
+        PartContext {
+            kind: PartKind::of(part),
+            text,
+        }
+    }
+}
+
+/// The `.git` directory of the repository `dir` is in. Worktrees and submodules have a `.git`
+/// file pointing to it instead.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+
+        let pointer = fs::read_to_string(&dot_git).ok()?;
+        let git_dir = Path::new(pointer.strip_prefix("gitdir:")?.trim());
+        Some(dir.join(git_dir))
+    })
+}
+
+/// Reads the branch from `HEAD` directly, so that rendering does not depend on `git` being
+/// installed
+fn git_branch(dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir(dir)?.join("HEAD")).ok()?;
+
+    head.trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("ref: refs/heads/main\n", Some("main"))]
+    #[case("ref: refs/heads/feature/x\n", Some("feature/x"))]
+    #[case("0123456789abcdef0123456789abcdef01234567\n", None)]
+    fn branch_is_read_from_head(#[case] head: &str, #[case] expected: Option<&str>) {
+        let repo = tempfile::tempdir().unwrap();
+        fs::create_dir_all(repo.path().join(".git")).unwrap();
+        fs::create_dir_all(repo.path().join("src/nested")).unwrap();
+        fs::write(repo.path().join(".git/HEAD"), head).unwrap();
+
+        assert_eq!(
+            git_branch(&repo.path().join("src/nested")).as_deref(),
+            expected
+        );
+    }
+
+    #[test]
+    fn worktree_points_to_its_git_dir() {
+        let repo = tempfile::tempdir().unwrap();
+        let git_dir = repo.path().join("main/.git/worktrees/wt");
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::create_dir_all(repo.path().join("wt")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/wt\n").unwrap();
+        fs::write(
+            repo.path().join("wt/.git"),
+            format!("gitdir: {}\n", git_dir.display()),
+        )
+        .unwrap();
+
+        assert_eq!(git_branch(&repo.path().join("wt")).as_deref(), Some("wt"));
+    }
+}
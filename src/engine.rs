@@ -1,78 +1,453 @@
-use std::io::Read;
-
 use crate::{
-    ast::{Part, Sentence, Span, parse_statement},
+    ast::{Part, Sentence, SentenceDefaults, Span, parse_statement_with},
+    config::Settings,
+    context::{FixtureContext, RenderContext},
+    error::{LakonikError, point_span},
     hir::{
         part::AnalyzedPart,
         sentence::AnalyzedSentence,
-        utils::{AnalysisContext, Analyzable},
+        utils::{AnalysisContext, Analyzable, Analyzed, PendingWrite, Resolution},
+        verb::resolve_verb,
+        vocative::is_vocative_name,
+    },
+    shell::{self, OnFailure, ShellError, ShellOutput, ShellPolicy, policy::DEFAULT_INTERPRETER},
+    templates::{
+        build_environment_with, functions::TemplatePolicy, metadata::OutputFormat, template_error,
     },
-    templates::build_environment,
 };
-use duct::cmd;
-use minijinja::{Environment, context};
+use futures::future::join_all;
+use lsp_types::{Position, Range};
+use minijinja::{Environment, Value, context};
 use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// The vocative and the verb that sentences may leave out, from `default_vocative` and
+/// `default_verb`. A verb template's own `default-vocative` wins over the configured one.
+#[derive(Clone, Debug, Default)]
+pub struct Defaults {
+    pub vocative: Option<String>,
+    pub verb: Option<String>,
+}
+
+impl From<&Settings> for Defaults {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            vocative: settings.default_vocative.clone(),
+            verb: settings.default_verb.clone(),
+        }
+    }
+}
+
+impl SentenceDefaults for Defaults {
+    fn is_verb(&self, name: &str) -> bool {
+        matches!(resolve_verb(name).1, Resolution::Exact | Resolution::Alias)
+    }
+
+    /// Ambiguous abbreviations count too, so that they are reported rather than taken for a
+    /// vocative. The name of a vocative never does, even if some verb starts with it.
+    fn abbreviates_verb(&self, name: &str) -> bool {
+        matches!(
+            resolve_verb(name).1,
+            Resolution::Prefix | Resolution::Ambiguous(_)
+        ) && !is_vocative_name(name)
+    }
+
+    fn vocative_for(&self, verb: &str) -> Option<String> {
+        resolve_verb(verb)
+            .0
+            .and_then(|t| t.metadata.default_vocative)
+            .or_else(|| self.vocative.clone())
+    }
 
-pub fn parse(input: &str) -> Sentence {
+    fn verb(&self) -> Option<String> {
+        self.verb.clone()
+    }
+}
+
+pub fn parse(input: &str, defaults: &Defaults) -> Result<Sentence, LakonikError> {
     let span = Span::new(input);
-    let (_, sentence) = parse_statement(span).expect("could not parse input");
 
-    sentence
+    parse_statement_with(span, defaults)
+        .map(|(_, sentence)| sentence)
+        .map_err(|err| {
+            let position = match &err {
+                nom::Err::Error(e) | nom::Err::Failure(e) => {
+                    Position::new(e.input.location_line() - 1, e.input.get_column() as u32 - 1)
+                }
+                nom::Err::Incomplete(_) => Position::default(),
+            };
+
+            LakonikError::Parse {
+                span: point_span(position),
+            }
+        })
+}
+
+/// Looks up a template, which compiles it, telling a missing template from a broken one
+fn load_template<'env>(
+    environment: &'env Environment,
+    name: &str,
+    span: &Range,
+) -> Result<minijinja::Template<'env, 'env>, LakonikError> {
+    environment.get_template(name).map_err(|source| {
+        if source.kind() == minijinja::ErrorKind::TemplateNotFound {
+            // Templates that don't load are left out of the environment, and only reported here
+            match template_error(name) {
+                Some(LakonikError::Render {
+                    template, source, ..
+                }) => LakonikError::Render {
+                    template,
+                    source,
+                    span: Some(*span),
+                },
+                Some(err) => err,
+                None => LakonikError::TemplateNotFound {
+                    name: name.to_string(),
+                    span: Some(*span),
+                },
+            }
+        } else {
+            LakonikError::Render {
+                template: name.to_string(),
+                source,
+                span: Some(*span),
+            }
+        }
+    })
+}
+
+/// Compiles every template the sentence will render, so that a broken or missing one fails the
+/// sentence before any of its shell code runs
+fn check_templates(
+    result: &AnalyzedSentence,
+    environment: &Environment,
+) -> Result<(), LakonikError> {
+    load_template(
+        environment,
+        &result.verb.template_name,
+        result.verb.get_range(),
+    )?;
+    if let Some(template) = &result.vocative.template {
+        load_template(environment, &template.path, result.vocative.get_range())?;
+    }
+    for part in &result.parts {
+        let (name, span) = match part {
+            AnalyzedPart::InlineShell(part) => ("parts/shell", &part.node.range),
+            AnalyzedPart::Stdin(part) => ("parts/stdin", &part.node.range),
+            _ => continue,
+        };
+        load_template(environment, name, span)?;
+    }
+
+    Ok(())
+}
+
+/// Renders a template, attributing any failure to `span` in the input
+pub fn render_template(
+    environment: &Environment,
+    name: &str,
+    context: Value,
+    span: &Range,
+) -> Result<String, LakonikError> {
+    load_template(environment, name, span)?
+        .render(context)
+        .map_err(|source| LakonikError::Render {
+            template: name.to_string(),
+            source,
+            span: Some(*span),
+        })
 }
 
-pub fn format_cmd_result(code: &str, environment: &Environment) -> String {
-    let cmd = cmd!("bash", "-c", code);
-    let mut reader = cmd
-        .stderr_to_stdout()
-        .reader()
-        .unwrap_or_else(|_| panic!("could not run command: {code}"));
-    let mut result = String::new();
+/// Output of one inline shell part, in the order the parts appear in the sentence
+#[derive(Debug, PartialEq, Clone)]
+pub struct ShellResult {
+    pub interpreter: String,
+    pub code: String,
+    pub span: Range,
+    pub output: ShellOutput,
+    pub duration: Duration,
+}
 
-    reader
-        .read_to_string(&mut result)
-        .expect("could not read command output");
+/// Runs every inline shell part, several at a time, and returns their results in source order
+pub async fn evaluate_shell_parts(
+    sentence: &AnalyzedSentence,
+    policy: &ShellPolicy,
+) -> Result<Vec<ShellResult>, LakonikError> {
+    let shell_error = |span: &Range| {
+        let span = *span;
+        move |source| LakonikError::Shell {
+            source,
+            span: Some(span),
+        }
+    };
+    let shell_parts = sentence
+        .parts
+        .iter()
+        .filter_map(|part| match part {
+            AnalyzedPart::InlineShell(part) => Some(&part.node),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
 
-    let template = environment.get_template("parts/shell").unwrap();
+    // Confirmation prompts can't overlap, so every part is authorized before any of them runs.
+    // They block on the terminal, which must not hold up the runtime.
+    let requests = shell_parts
+        .iter()
+        .map(|part| (part.interpreter.clone(), part.code.clone(), part.range))
+        .collect::<Vec<_>>();
+    let authorizing = policy.clone();
+    tokio::task::spawn_blocking(move || {
+        requests.iter().try_for_each(|(interpreter, code, span)| {
+            shell::authorize(interpreter.as_deref(), code, &authorizing).map_err(shell_error(span))
+        })
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))?;
+
+    let slots = Semaphore::new(policy.max_parallel.max(1));
+    let results = join_all(shell_parts.iter().map(|part| async {
+        let _slot = slots.acquire().await.expect("semaphore is never closed");
+        let started = Instant::now();
+        let output = shell::execute(part.interpreter.as_deref(), &part.code, policy).await;
+
+        (output, started.elapsed())
+    }))
+    .await;
+
+    shell_parts
+        .into_iter()
+        .zip(results)
+        .map(|(part, (output, duration))| {
+            let output = output.map_err(shell_error(&part.range))?;
+            if !output.success() && policy.on_failure == OnFailure::Abort {
+                return Err(shell_error(&part.range)(ShellError::Failed {
+                    code: part.code.clone(),
+                    output,
+                }));
+            }
+
+            Ok(ShellResult {
+                interpreter: part
+                    .interpreter
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_INTERPRETER.to_string()),
+                code: part.code.clone(),
+                span: part.range,
+                output,
+                duration,
+            })
+        })
+        .collect()
+}
+
+pub fn format_cmd_result(
+    result: &ShellResult,
+    environment: &Environment,
+) -> Result<String, LakonikError> {
     let context = context! {
-        code,
-        result,
+        interpreter => result.interpreter,
+        code => result.code,
+        stdout => result.output.stdout,
+        stderr => result.output.stderr,
+        exit_code => result.output.exit_code,
+        signal => result.output.signal,
+        // Kept for templates written before stdout and stderr were separated
+        result => result.output.stdout,
     };
 
-    template.render(context).unwrap()
+    render_template(environment, "parts/shell", context, &result.span)
 }
 
-pub fn extract_description(sentence: &AnalyzedSentence, environment: &Environment) -> String {
-    sentence
+fn format_stdin(
+    stdin: &str,
+    span: &Range,
+    environment: &Environment,
+) -> Result<String, LakonikError> {
+    let context = context! {
+        content => stdin,
+        bytes => stdin.len(),
+    };
+
+    render_template(environment, "parts/stdin", context, span)
+}
+
+pub fn extract_description(
+    sentence: &AnalyzedSentence,
+    environment: &Environment,
+    shell_results: &[ShellResult],
+    inputs: &PromptInputs,
+    on_failure: OnFailure,
+) -> Result<String, LakonikError> {
+    let mut shell_results = shell_results.iter();
+    let descriptions = sentence
         .parts
         .iter()
         .filter_map(|part| match &part {
-            AnalyzedPart::Freeform(part) => Some(part.node.text.clone()),
-            AnalyzedPart::InlineShell(part) => {
-                Some(format_cmd_result(part.node.code.as_str(), environment))
-            }
+            AnalyzedPart::Freeform(part) => Some(Ok(part.node.text.clone())),
+            AnalyzedPart::InlineShell(_) => shell_results
+                .next()
+                .filter(|result| result.output.success() || on_failure != OnFailure::Omit)
+                .map(|result| format_cmd_result(result, environment)),
+            AnalyzedPart::Stdin(part) => Some(format_stdin(
+                inputs.stdin.as_deref().unwrap_or_default(),
+                &part.node.range,
+                environment,
+            )),
             _ => None,
         })
-        .collect::<Vec<_>>()
-        .join(" ")
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(descriptions.join(" "))
+}
+
+/// Every template a sentence can render, with the template functions bound by `policy`
+pub fn sentence_environment(
+    result: &AnalyzedSentence,
+    policy: &TemplatePolicy,
+) -> Result<Environment<'static>, LakonikError> {
+    let mut environment = build_environment_with(policy)?;
+    // Assigned templates are rendered from memory, since they may not have been saved yet
+    if let Some(contents) = &result.verb.definition {
+        environment
+            .add_template_owned(result.verb.template_name.clone(), contents.clone())
+            .map_err(|source| LakonikError::Render {
+                template: result.verb.template_name.clone(),
+                source,
+                span: Some(*result.verb.get_range()),
+            })?;
+    }
+
+    Ok(environment)
+}
+
+pub fn build_prompt(
+    result: &AnalyzedSentence,
+    shell_results: &[ShellResult],
+    inputs: &PromptInputs,
+    policy: &ShellPolicy,
+    environment: &Environment,
+) -> Result<String, LakonikError> {
+    let description = extract_description(
+        result,
+        environment,
+        shell_results,
+        inputs,
+        policy.on_failure,
+    )?;
+    let context = RenderContext::new(result, description, shell_results, inputs.fixture.as_ref());
+
+    render_template(
+        environment,
+        &result.verb.template_name,
+        Value::from_serialize(context),
+        result.verb.get_range(),
+    )
 }
 
-pub fn build_prompt(result: &AnalyzedSentence) -> String {
-    result.verb.ensure_template();
-    let environment = build_environment();
+/// Budget of file attachments and piped input, unless configured otherwise
+pub const DEFAULT_MAX_ATTACHMENT_BYTES: u64 = 10 * 1024 * 1024;
 
-    let description = extract_description(result, &environment);
+/// Inputs of a prompt that don't come from the sentence itself
+#[derive(Debug, Clone)]
+pub struct PromptInputs {
+    /// Data piped into `lakonik`, used by `-` and `@-` parts
+    pub stdin: Option<String>,
+    /// Combined size that file attachments and piped input may not exceed
+    pub max_attachment_bytes: u64,
+    /// What template functions such as `env(name)` may read. Its shell policy and read budget
+    /// are replaced by the ones of the prompt being built.
+    pub template_policy: TemplatePolicy,
+    /// What the sentence may leave out
+    pub defaults: Defaults,
+    /// Set when a fixture renders the sentence, which pins whatever depends on the machine
+    pub fixture: Option<FixtureContext>,
+}
+
+impl Default for PromptInputs {
+    fn default() -> Self {
+        Self {
+            stdin: None,
+            max_attachment_bytes: DEFAULT_MAX_ATTACHMENT_BYTES,
+            template_policy: TemplatePolicy::default(),
+            defaults: Defaults::default(),
+            fixture: None,
+        }
+    }
+}
+
+/// Whether the sentence uses piped input, so that it only has to be read when needed
+pub fn reads_stdin(sentence: &Sentence) -> bool {
+    stdin_span(sentence).is_some()
+}
+
+/// The first part that uses piped input
+pub fn stdin_span(sentence: &Sentence) -> Option<Range> {
+    sentence.parts.iter().find_map(|p| match p {
+        Part::Stdin(part) => Some(part.range),
+        _ => None,
+    })
+}
+
+/// Fails on the first attachment that takes the combined size over the budget, or else returns
+/// the combined size
+fn check_attachment_budget(
+    sentence: &Sentence,
+    inputs: &PromptInputs,
+) -> Result<u64, LakonikError> {
+    let mut total = 0;
+    let mut stdin_counted = false;
+
+    for part in &sentence.parts {
+        let (size, span) = match part {
+            // Missing files are left for the consumer of the attachments to report
+            Part::FilePath(p) => (std::fs::metadata(&p.path).map_or(0, |m| m.len()), p.range),
+            // Piped input is only read once, no matter how often it is used
+            Part::Stdin(p) if !stdin_counted => {
+                stdin_counted = true;
+                (inputs.stdin.as_ref().map_or(0, |s| s.len() as u64), p.range)
+            }
+            _ => continue,
+        };
+
+        total += size;
+        if total > inputs.max_attachment_bytes {
+            return Err(LakonikError::AttachmentsTooLarge {
+                limit: inputs.max_attachment_bytes,
+                span: Some(span),
+            });
+        }
+    }
+
+    Ok(total)
+}
+
+/// Renders the system prompt for the vocative of the sentence
+pub fn build_system_prompt(
+    result: &AnalyzedSentence,
+    output: OutputFormat,
+    environment: &Environment,
+) -> Result<Option<String>, LakonikError> {
+    let Some(template) = &result.vocative.template else {
+        return Ok(None);
+    };
     let context = context! {
-        description,
+        vocative => result.vocative.node.name,
+        verb => result.verb.node.name(),
+        output,
     };
-    let template = environment
-        .get_template(&result.verb.template_name)
-        .unwrap();
 
-    template.render(context).unwrap()
+    render_template(
+        environment,
+        &template.path,
+        context,
+        result.vocative.get_range(),
+    )
+    .map(Some)
 }
 
-pub fn extract_attachments(sentence: &Sentence) -> Vec<Attachment> {
-    sentence
+pub fn extract_attachments(sentence: &Sentence, inputs: &PromptInputs) -> Vec<Attachment> {
+    let mut attachments = sentence
         .parts
         .iter()
         .filter_map(|p| {
@@ -84,7 +459,15 @@ pub fn extract_attachments(sentence: &Sentence) -> Vec<Attachment> {
                 None
             }
         })
-        .collect()
+        .collect::<Vec<_>>();
+
+    if reads_stdin(sentence) {
+        attachments.push(Attachment::Stdin(StdinAttachment {
+            bytes: inputs.stdin.as_ref().map_or(0, String::len),
+        }));
+    }
+
+    attachments
 }
 
 #[derive(Debug, PartialEq, Serialize)]
@@ -92,37 +475,97 @@ pub struct FileAttachment {
     pub path: String,
 }
 
+/// Piped input is already part of the prompt, so only its size is reported
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StdinAttachment {
+    pub bytes: usize,
+}
+
 #[derive(Debug, PartialEq, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Attachment {
     File(FileAttachment),
+    Stdin(StdinAttachment),
 }
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct PromptBuilderResult {
     pub ast: Sentence,
+    /// The vocative as resolved from an alias or an abbreviation, e.g. `qwen3` for `q3`
+    pub vocative: String,
+    /// The verb as resolved from an alias or an abbreviation, e.g. `create` for `cr`
+    pub verb: String,
     pub attachments: Vec<Attachment>,
+    /// System prompt for the vocative, from its template or the shared default
+    pub system: Option<String>,
     pub prompt: String,
+    /// What the verb expects the answer to look like
+    pub output: OutputFormat,
+    /// Templates defined by the sentence, saved by `eval` unless it runs with `--no-persist`
+    pub pending_writes: Vec<PendingWrite>,
+    #[serde(skip)]
+    pub shell_results: Vec<ShellResult>,
 }
 
-pub fn run_prompt_builder(raw_input: &str) -> PromptBuilderResult {
-    let ast = parse(raw_input);
-    let hir = ast.analyze(&mut AnalysisContext {});
-    let prompt = build_prompt(&hir);
-    let attachments = extract_attachments(&ast);
+pub async fn run_prompt_builder(
+    raw_input: &str,
+    inputs: &PromptInputs,
+    policy: &ShellPolicy,
+) -> Result<PromptBuilderResult, LakonikError> {
+    let ast = parse(raw_input, &inputs.defaults)?;
+    let attached = check_attachment_budget(&ast, inputs)?;
+    let mut ctx = AnalysisContext {
+        fixture: inputs.fixture.is_some(),
+        ..AnalysisContext::default()
+    };
+    let hir = ast.analyze(&mut ctx);
+    if let Some(issue) = hir.issues.first() {
+        return Err(LakonikError::Invalid {
+            message: issue.message.clone(),
+            span: Some(issue.range),
+        });
+    }
+    let output = hir
+        .verb
+        .template
+        .as_ref()
+        .map(|t| t.metadata.output)
+        .unwrap_or_default();
+    // Template functions read from the same budget as attachments and run under the same
+    // shell policy as the code in the sentence
+    let template_policy = TemplatePolicy {
+        max_read_bytes: inputs.max_attachment_bytes.saturating_sub(attached),
+        shell: policy.clone(),
+        files: inputs.fixture.as_ref().map(|fixture| fixture.files.clone()),
+        ..inputs.template_policy.clone()
+    };
+    let environment = sentence_environment(&hir, &template_policy)?;
+    check_templates(&hir, &environment)?;
+    let shell_results = evaluate_shell_parts(&hir, policy).await?;
+    let prompt = build_prompt(&hir, &shell_results, inputs, policy, &environment)?;
+    let attachments = extract_attachments(&ast, inputs);
+    let system = build_system_prompt(&hir, output, &environment)?;
 
-    PromptBuilderResult {
+    Ok(PromptBuilderResult {
         ast,
+        vocative: hir.vocative.name.clone(),
+        verb: hir.verb.name.clone(),
         attachments,
+        system,
         prompt,
-    }
+        output,
+        pending_writes: ctx.pending_writes,
+        shell_results,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::templates::assignment_contents;
     use insta::assert_yaml_snapshot;
     use rstest::rstest;
+    use serial_test::serial;
 
     #[derive(Debug, PartialEq, Serialize)]
     struct SimplifiedPromptBuilderResult {
@@ -136,23 +579,26 @@ mod tests {
     #[case("qwen3 create $(echo \"hello\nworld\" | grep world)")]
     #[case("robot ~testverbdeleteme1=(test template delete me: ) $(expr 5 - 3)")]
     #[case("robot ~testverbdeleteme2 = (hello)")]
-    fn parse_statement_snapshot(#[case] input: &str) {
-        let tmp = tempfile::tempdir().unwrap();
-        unsafe {
-            std::env::set_var("LAKONIK_CONFIG", tmp.path());
-        }
+    #[case("robot ~testverbdeleteme3 := (one shot:) foo")]
+    #[case("robot ~testverbdeleteme4=(<task>{{ description }}</task>) foo")]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn parse_statement_snapshot(#[case] input: &str) {
+        let _user_dir = crate::templates::TestUserDir::new();
         let user_templates = crate::templates::get_user_templates();
         let test_templates = user_templates
             .filter(|t| t.path.starts_with("verbs/testverbdeleteme"))
             .collect::<Vec<_>>();
         for template in test_templates {
-            crate::templates::delete_user_template(&template.path);
+            crate::templates::delete_user_template(&template.path).unwrap();
         }
 
         let mut s = insta::Settings::clone_current();
         s.set_snapshot_suffix(input.replace(' ', "_").to_string());
         let _guard = s.bind_to_scope();
-        let results = run_prompt_builder(input);
+        let results = run_prompt_builder(input, &PromptInputs::default(), &ShellPolicy::default())
+            .await
+            .unwrap();
         let simplified_result = SimplifiedPromptBuilderResult {
             attachments: results.attachments,
             prompt: results.prompt,
@@ -160,4 +606,516 @@ mod tests {
 
         assert_yaml_snapshot!(simplified_result);
     }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn assignments_define_overwrite_or_stay_ephemeral() {
+        let tmp = crate::templates::TestUserDir::new();
+        let prompt = |input: &'static str| async move {
+            let result =
+                run_prompt_builder(input, &PromptInputs::default(), &ShellPolicy::default())
+                    .await
+                    .unwrap();
+            for write in &result.pending_writes {
+                write.commit().unwrap();
+            }
+
+            result.prompt
+        };
+
+        assert_eq!(prompt("robot ~testverbassign=(old) foo").await, "old foo");
+        assert_eq!(
+            prompt("robot ~testverbassign=(ignored) foo").await,
+            "old foo"
+        );
+        assert_eq!(
+            prompt("robot ~testverbassign:=(once) foo").await,
+            "once foo"
+        );
+        assert_eq!(prompt("robot ~testverbassign!=(new) foo").await, "new foo");
+        assert_eq!(prompt("robot testverbassign foo").await, "new foo");
+        assert_eq!(prompt("robot ~testverbonce:=(once) foo").await, "once foo");
+        assert!(!tmp.path().join("verbs/testverbonce").exists());
+        assert_eq!(
+            prompt("robot ~create=(mine) foo").await,
+            prompt("robot create foo").await
+        );
+        assert!(!tmp.path().join("verbs/create").exists());
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn assignments_are_only_planned() {
+        let tmp = crate::templates::TestUserDir::new();
+
+        let result = run_prompt_builder(
+            "robot ~testverbplanned=(planned) foo",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.prompt, "planned foo");
+        assert_eq!(
+            result.pending_writes,
+            vec![PendingWrite::Template {
+                path: "verbs/testverbplanned".to_string(),
+                contents: assignment_contents("planned"),
+            }]
+        );
+        assert!(!tmp.path().join("verbs/testverbplanned").exists());
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn left_out_vocative_and_verb_are_inferred() {
+        let inputs = PromptInputs {
+            defaults: Defaults {
+                vocative: Some("robot".to_string()),
+                verb: Some("create".to_string()),
+            },
+            ..PromptInputs::default()
+        };
+        let explicit = run_prompt_builder("robot create foo", &inputs, &ShellPolicy::default())
+            .await
+            .unwrap();
+
+        for input in ["create foo", "robot foo"] {
+            let result = run_prompt_builder(input, &inputs, &ShellPolicy::default())
+                .await
+                .unwrap();
+            assert_eq!(result.prompt, explicit.prompt);
+            assert_eq!(result.ast.vocative.name, "robot");
+            assert_eq!(result.ast.verb.name(), "create");
+        }
+
+        let json = serde_json::to_value(
+            run_prompt_builder("create foo", &inputs, &ShellPolicy::default())
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json["ast"]["vocative"]["inferred"], true);
+        assert!(json["ast"]["verb"].get("inferred").is_none());
+
+        let analyzed = parse("create foo", &inputs.defaults)
+            .unwrap()
+            .analyze(&mut AnalysisContext::default());
+        assert!(analyzed.verb.hover_text.contains("**robot** (inferred"));
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn broken_templates_only_fail_the_sentences_that_use_them() {
+        let _user_dir = crate::templates::TestUserDir::new();
+        let templates = [
+            ("verbs/testbrokenbody", "{% if %}"),
+            (
+                "verbs/testbrokenmeta",
+                "+++\naliases = 1\n+++\n{{ description }}",
+            ),
+        ];
+        for (path, contents) in templates {
+            crate::templates::create_user_template(path, contents).unwrap();
+        }
+        let prompt = |input: &'static str| async move {
+            run_prompt_builder(input, &PromptInputs::default(), &ShellPolicy::default()).await
+        };
+
+        assert!(prompt("qwen3 create foo").await.is_ok());
+        let body = prompt("qwen3 testbrokenbody foo").await.unwrap_err();
+        assert!(
+            matches!(&body, LakonikError::Render { template, .. } if template == "verbs/testbrokenbody"),
+            "{body:?}"
+        );
+        assert_eq!(body.exit_code(), 70);
+        let meta = prompt("qwen3 testbrokenmeta foo").await.unwrap_err();
+        assert_eq!(meta.exit_code(), 78, "{meta:?}");
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn vocative_names_are_not_taken_for_abbreviated_verbs() {
+        let _user_dir = crate::templates::TestUserDir::new();
+        for path in ["vocatives/testvocrobot", "verbs/testvocrobotize"] {
+            crate::templates::create_user_template(path, "{{ description }}").unwrap();
+        }
+        let defaults = Defaults {
+            vocative: Some("qwen3".to_string()),
+            verb: Some("create".to_string()),
+        };
+
+        let sentence = parse("testvocrobot foo", &defaults).unwrap();
+        assert_eq!(sentence.vocative.name, "testvocrobot");
+        assert_eq!(sentence.verb.name(), "create");
+        assert!(sentence.verb.is_inferred());
+
+        let sentence = parse("testvocrobotiz foo", &defaults).unwrap();
+        assert_eq!(sentence.vocative.name, "qwen3");
+        assert_eq!(sentence.verb.name(), "testvocrobotiz");
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn only_full_verb_names_replace_the_default_verb() {
+        let defaults = Defaults {
+            vocative: None,
+            verb: Some("create".to_string()),
+        };
+
+        let sentence = parse("qwen3 c the bug", &defaults).unwrap();
+        assert!(sentence.verb.is_inferred());
+        assert!(matches!(&sentence.parts[0], Part::Freeform(p) if p.text == "c"));
+
+        let sentence = parse("qwen3 create the bug", &defaults).unwrap();
+        assert!(!sentence.verb.is_inferred());
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn abbreviations_resolve_to_full_names() {
+        let full = run_prompt_builder(
+            "qwen3 create foo",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+        let short = run_prompt_builder(
+            "qw cr foo",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(short.prompt, full.prompt);
+        assert_eq!(short.system, full.system);
+        assert_eq!(
+            (short.vocative.as_str(), short.verb.as_str()),
+            ("qwen3", "create")
+        );
+        assert_eq!(short.ast.verb.name(), "cr");
+
+        let analyzed = parse("qw cr foo", &Defaults::default())
+            .unwrap()
+            .analyze(&mut AnalysisContext::default());
+        assert!(
+            analyzed
+                .verb
+                .hover_text
+                .contains("`cr` is short for **create**")
+        );
+        assert!(
+            analyzed
+                .vocative
+                .hover_text
+                .contains("`qw` is short for **qwen3**")
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn verb_templates_receive_the_sentence() {
+        let result = run_prompt_builder(
+            "robot ~testverbcontext:=({{ vocative }} {{ verb }} {% for part in parts %}\
+             {{ part.kind }}={{ part.text }} {% endfor %}{{ attachments[0].contents }}\
+             {{ shell[0].stdout }}{{ os }}) foo @Cargo.toml $(echo hi)",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+
+        let cargo_toml = std::fs::read_to_string("Cargo.toml").unwrap();
+        assert_eq!(
+            result.prompt,
+            format!(
+                "robot testverbcontext freeform=foo file=Cargo.toml shell=echo hi \
+                 {cargo_toml}hi\n{}",
+                std::env::consts::OS
+            )
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn shell_policy_violations_are_errors() {
+        let policy = ShellPolicy {
+            deny: vec!["expr".to_string()],
+            ..ShellPolicy::default()
+        };
+
+        let result = run_prompt_builder(
+            "qwen3 create $(expr 2 + 3)",
+            &PromptInputs::default(),
+            &policy,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(LakonikError::Shell {
+                source: ShellError::Denied { .. },
+                span: Some(_),
+            })
+        ));
+    }
+
+    #[rstest]
+    #[case("qwen3", 65, Some((0, 5)))]
+    #[case("qwen3 create foo!", 65, Some((0, 16)))]
+    #[case("qwen3 doesnotexist foo", 66, Some((0, 6)))]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn errors_carry_exit_codes_and_spans(
+        #[case] input: &str,
+        #[case] exit_code: u8,
+        #[case] span_start: Option<(u32, u32)>,
+    ) {
+        let error = run_prompt_builder(input, &PromptInputs::default(), &ShellPolicy::default())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.exit_code(), exit_code);
+        assert_eq!(
+            error.span().map(|s| (s.start.line, s.start.character)),
+            span_start
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn shell_parts_run_concurrently_and_stay_in_order() {
+        let policy = ShellPolicy {
+            max_parallel: 3,
+            ..ShellPolicy::default()
+        };
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("log");
+        let log = log.display();
+
+        let results = run_prompt_builder(
+            &format!(
+                "qwen3 create $(echo start a >> {log}; sleep 0.3; echo end a >> {log}; echo a) \
+                 $(echo start b >> {log}; sleep 0.3; echo end b >> {log}; echo b) $(echo c)"
+            ),
+            &PromptInputs::default(),
+            &policy,
+        )
+        .await
+        .unwrap();
+
+        let outputs = results
+            .shell_results
+            .iter()
+            .map(|r| r.output.stdout.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(outputs, vec!["a\n", "b\n", "c\n"]);
+        assert!(results.shell_results[0].duration >= Duration::from_millis(300));
+
+        // Each part started before the other one ended
+        let log = std::fs::read_to_string(tmp.path().join("log")).unwrap();
+        let line = |marker| log.lines().position(|l| l == marker).unwrap();
+        assert!(line("start a") < line("end b"), "{log}");
+        assert!(line("start b") < line("end a"), "{log}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn broken_templates_fail_before_shell_code_runs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let marker = tmp.path().join("marker");
+
+        let err = run_prompt_builder(
+            &format!(
+                "robot ~testverbbroken:=({{% if description %}}) $(touch {})",
+                marker.display()
+            ),
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, LakonikError::Render { .. }), "{err:?}");
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn concurrency_limit_is_respected() {
+        let policy = ShellPolicy {
+            max_parallel: 1,
+            ..ShellPolicy::default()
+        };
+        let started = Instant::now();
+
+        run_prompt_builder(
+            "qwen3 create $(sleep 0.2) $(sleep 0.2)",
+            &PromptInputs::default(),
+            &policy,
+        )
+        .await
+        .unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[rstest]
+    #[case(
+        OnFailure::Warn,
+        "create for me a(n) \n\nCommand (bash): echo oops >&2; exit 2\nOutput:\n\nErrors:\noops\nExit status: 2\n foo"
+    )]
+    #[case(OnFailure::Omit, "create for me a(n) foo")]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn failing_shell_parts_follow_policy(
+        #[case] on_failure: OnFailure,
+        #[case] prompt: &str,
+    ) {
+        let policy = ShellPolicy {
+            on_failure,
+            ..ShellPolicy::default()
+        };
+
+        let results = run_prompt_builder(
+            "qwen3 create $(echo oops >&2; exit 2) foo",
+            &PromptInputs::default(),
+            &policy,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(results.prompt, prompt);
+        assert_eq!(results.shell_results[0].output.exit_code, Some(2));
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn killed_shell_parts_name_the_signal() {
+        let results = run_prompt_builder(
+            "qwen3 create $(kill -9 $$)",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            results.prompt,
+            "create for me a(n) \n\nCommand (bash): kill -9 $$\nOutput:\n\nKilled by signal 9\n"
+        );
+        assert_eq!(results.shell_results[0].output.signal, Some(9));
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn failing_shell_parts_can_abort() {
+        let policy = ShellPolicy {
+            on_failure: OnFailure::Abort,
+            ..ShellPolicy::default()
+        };
+
+        let error = run_prompt_builder(
+            "qwen3 create $(echo oops >&2; exit 2)",
+            &PromptInputs::default(),
+            &policy,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "`echo oops >&2; exit 2` exited with status 2: oops"
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn tagged_parts_use_their_interpreter() {
+        let results = run_prompt_builder(
+            "qwen3 create $sh(echo $0)",
+            &PromptInputs::default(),
+            &ShellPolicy::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            results.prompt,
+            "create for me a(n) \n\nCommand (sh): echo $0\nOutput:\nsh\n\n"
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn piped_input_is_rendered_and_attached() {
+        let inputs = PromptInputs {
+            stdin: Some("diff --git a/foo b/foo\n".to_string()),
+            ..PromptInputs::default()
+        };
+
+        let results = run_prompt_builder("qwen3 create - foo", &inputs, &ShellPolicy::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results.prompt,
+            "create for me a(n) \n\nInput:\ndiff --git a/foo b/foo\n foo"
+        );
+        assert_eq!(
+            results.attachments,
+            vec![Attachment::Stdin(StdinAttachment { bytes: 23 })]
+        );
+    }
+
+    #[rstest]
+    #[case("qwen3 create @- @-", 4, None)]
+    #[case("qwen3 create @- @-", 3, Some(13))]
+    #[case("qwen3 create @Cargo.toml -", 4, Some(14))]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn attachments_respect_budget(
+        #[case] input: &str,
+        #[case] max_attachment_bytes: u64,
+        #[case] failing_part: Option<u32>,
+    ) {
+        let inputs = PromptInputs {
+            stdin: Some("data".to_string()),
+            max_attachment_bytes,
+            ..PromptInputs::default()
+        };
+
+        let result = run_prompt_builder(input, &inputs, &ShellPolicy::default()).await;
+
+        match failing_part {
+            None => assert!(result.is_ok()),
+            Some(character) => {
+                let error = result.unwrap_err();
+                assert!(matches!(error, LakonikError::AttachmentsTooLarge { .. }));
+                assert_eq!(error.span().unwrap().start.character, character);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(
+        "qwen3 create foo",
+        "You are a helpful assistant. Answer with exactly what was asked for."
+    )]
+    #[case(
+        "robot create foo",
+        "You are a helpful assistant. Answer with exactly what was asked for."
+    )]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn system_prompt_comes_from_vocative(#[case] input: &str, #[case] system: &str) {
+        let results = run_prompt_builder(input, &PromptInputs::default(), &ShellPolicy::default())
+            .await
+            .unwrap();
+
+        assert_eq!(results.system.as_deref(), Some(system));
+    }
 }
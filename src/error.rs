@@ -0,0 +1,190 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use lsp_types::{Position, Range};
+
+use crate::shell::ShellError;
+
+/// Everything that can go wrong while turning a sentence into a prompt
+#[derive(Debug)]
+pub enum LakonikError {
+    Parse {
+        span: Range,
+    },
+    TemplateNotFound {
+        name: String,
+        span: Option<Range>,
+    },
+    Render {
+        template: String,
+        source: minijinja::Error,
+        span: Option<Range>,
+    },
+    Shell {
+        source: ShellError,
+        span: Option<Range>,
+    },
+    AttachmentsTooLarge {
+        limit: u64,
+        span: Option<Range>,
+    },
+    Invalid {
+        message: String,
+        span: Option<Range>,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Config {
+        message: String,
+    },
+}
+
+impl LakonikError {
+    /// Where in the input the error comes from, if it can be pinned down
+    pub fn span(&self) -> Option<&Range> {
+        match self {
+            LakonikError::Parse { span } => Some(span),
+            LakonikError::TemplateNotFound { span, .. }
+            | LakonikError::Render { span, .. }
+            | LakonikError::Shell { span, .. }
+            | LakonikError::AttachmentsTooLarge { span, .. }
+            | LakonikError::Invalid { span, .. } => span.as_ref(),
+            LakonikError::Io { .. } | LakonikError::Config { .. } => None,
+        }
+    }
+
+    /// Process exit code, following the conventions of `sysexits.h`
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            LakonikError::Parse { .. }
+            | LakonikError::AttachmentsTooLarge { .. }
+            | LakonikError::Invalid { .. } => 65,
+            LakonikError::TemplateNotFound { .. } => 66,
+            LakonikError::Shell { .. } => 69,
+            LakonikError::Render { .. } => 70,
+            LakonikError::Io { .. } => 74,
+            LakonikError::Config { .. } => 78,
+        }
+    }
+
+    /// Formats the error for humans, underlining the offending part of `input`
+    pub fn report(&self, input: &str) -> String {
+        let mut report = format!("error: {self}");
+
+        if let Some(span) = self.span() {
+            report.push_str(&annotate(input, span));
+        }
+
+        report
+    }
+}
+
+fn annotate(input: &str, span: &Range) -> String {
+    let Some(line) = input.lines().nth(span.start.line as usize) else {
+        return String::new();
+    };
+
+    let line_number = (span.start.line + 1).to_string();
+    let gutter = " ".repeat(line_number.len());
+    let start = (span.start.character as usize).min(line.len());
+    let end = if span.end.line == span.start.line {
+        (span.end.character as usize).clamp(start + 1, line.len().max(start + 1))
+    } else {
+        line.len().max(start + 1)
+    };
+
+    format!(
+        "\n{gutter}--> {}:{}\n{gutter} |\n{line_number} | {line}\n{gutter} | {}{}",
+        span.start.line + 1,
+        span.start.character + 1,
+        " ".repeat(start),
+        "^".repeat(end - start),
+    )
+}
+
+impl fmt::Display for LakonikError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LakonikError::Parse { .. } => write!(
+                f,
+                "could not parse input; expected `<vocative> <verb> [parts...]`"
+            ),
+            LakonikError::TemplateNotFound { name, .. } => {
+                write!(f, "template `{name}` does not exist")
+            }
+            LakonikError::Render {
+                template, source, ..
+            } => write!(f, "could not render template `{template}`: {source}"),
+            LakonikError::Shell { source, .. } => write!(f, "{source}"),
+            LakonikError::AttachmentsTooLarge { limit, .. } => {
+                write!(f, "attachments exceed the budget of {limit} bytes")
+            }
+            LakonikError::Invalid { message, .. } => write!(f, "{message}"),
+            LakonikError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            LakonikError::Config { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for LakonikError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LakonikError::Render { source, .. } => Some(source),
+            LakonikError::Shell { source, .. } => Some(source),
+            LakonikError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A span that starts at `position` and covers the rest of its line
+pub fn point_span(position: Position) -> Range {
+    Range::new(position, Position::new(position.line, u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_underlines_span() {
+        let error = LakonikError::TemplateNotFound {
+            name: "verbs/nope".to_string(),
+            span: Some(Range::new(Position::new(0, 6), Position::new(0, 10))),
+        };
+
+        assert_eq!(
+            error.report("qwen3 nope foo"),
+            "error: template `verbs/nope` does not exist\n \
+             --> 1:7\n  |\n1 | qwen3 nope foo\n  |       ^^^^"
+        );
+    }
+
+    #[test]
+    fn report_without_span() {
+        let error = LakonikError::Config {
+            message: "no user template directory".to_string(),
+        };
+
+        assert_eq!(
+            error.report("qwen3 nope"),
+            "error: no user template directory"
+        );
+    }
+
+    #[test]
+    fn point_spans_reach_end_of_line() {
+        let error = LakonikError::Parse {
+            span: point_span(Position::new(1, 2)),
+        };
+
+        assert!(
+            error
+                .report("foo\nbar baz")
+                .ends_with("2 | bar baz\n  |   ^^^^^")
+        );
+    }
+}
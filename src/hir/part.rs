@@ -1,4 +1,6 @@
-use crate::ast::{FilePathPart, FreeformPart, InlineShellPart, Part};
+use std::path::Path;
+
+use crate::ast::{FilePathPart, FreeformPart, InlineShellPart, Part, StdinPart};
 
 use super::utils::{AnalysisContext, Analyzable};
 
@@ -11,6 +13,7 @@ pub struct AnalyzedFreeformPart {
 #[derive(Clone, Debug)]
 pub struct AnalyzedFilePathPart {
     pub node: FilePathPart,
+    pub exists: bool,
     pub hover_text: String,
 }
 
@@ -20,11 +23,18 @@ pub struct AnalyzedInlineShellPart {
     pub hover_text: String,
 }
 
+#[derive(Clone, Debug)]
+pub struct AnalyzedStdinPart {
+    pub node: StdinPart,
+    pub hover_text: String,
+}
+
 #[derive(Clone, Debug)]
 pub enum AnalyzedPart {
     Freeform(AnalyzedFreeformPart),
     FilePath(AnalyzedFilePathPart),
     InlineShell(AnalyzedInlineShellPart),
+    Stdin(AnalyzedStdinPart),
 }
 
 impl Analyzable for Part {
@@ -38,12 +48,22 @@ impl Analyzable for Part {
             }),
             Part::FilePath(part) => AnalyzedPart::FilePath(AnalyzedFilePathPart {
                 node: part.clone(),
+                exists: Path::new(&part.path).exists(),
                 hover_text: "This is a file path part".to_string(),
             }),
             Part::InlineShell(part) => AnalyzedPart::InlineShell(AnalyzedInlineShellPart {
                 node: part.clone(),
-                hover_text: format!("Will expand to the results of `{}`", { part.code.clone() })
-                    .to_string(),
+                hover_text: match &part.interpreter {
+                    Some(interpreter) => format!(
+                        "Will expand to the results of `{}`, run with `{interpreter}`",
+                        part.code
+                    ),
+                    None => format!("Will expand to the results of `{}`", part.code),
+                },
+            }),
+            Part::Stdin(part) => AnalyzedPart::Stdin(AnalyzedStdinPart {
+                node: part.clone(),
+                hover_text: "Will expand to the data piped into standard input".to_string(),
             }),
         }
     }
@@ -1,9 +1,9 @@
-use crate::ast::Sentence;
+use crate::ast::{Part, Sentence};
 
 use super::{
     part::AnalyzedPart,
-    utils::{AnalysisContext, Analyzable},
-    verb::AnalyzedVerb,
+    utils::{AnalysisContext, AnalysisIssue, Analyzable, Analyzed, Resolution},
+    verb::{AnalyzedVerb, misspelled_verb},
     vocative::AnalyzedVocative,
 };
 
@@ -14,18 +14,82 @@ pub struct AnalyzedSentence {
     pub vocative: AnalyzedVocative,
     pub verb: AnalyzedVerb,
     pub parts: Vec<AnalyzedPart>,
+    pub issues: Vec<AnalysisIssue>,
+    /// Likely mistakes that don't keep the sentence from being evaluated
+    pub hints: Vec<AnalysisIssue>,
+}
+
+fn ambiguous(kind: &str, typed: &str, candidates: &[String]) -> String {
+    format!(
+        "ambiguous {kind} `{typed}`, it could be `{}`",
+        candidates.join("`, `")
+    )
+}
+
+/// With a default verb, a mistyped verb silently becomes the first word of the description
+fn mistyped_verb(sentence: &Sentence) -> Option<AnalysisIssue> {
+    if !sentence.verb.is_inferred() {
+        return None;
+    }
+    let Some(Part::Freeform(word)) = sentence.parts.first() else {
+        return None;
+    };
+    let verb = misspelled_verb(&word.text)?;
+
+    Some(AnalysisIssue {
+        range: word.range,
+        message: format!(
+            "`{}` is not a verb, so the default verb is used; did you mean `{verb}`?",
+            word.text
+        ),
+    })
 }
 
 impl Analyzable for Sentence {
     type AnalyzedNode = AnalyzedSentence;
 
-    fn analyze(&self, _ctx: &mut AnalysisContext) -> Self::AnalyzedNode {
+    fn analyze(&self, ctx: &mut AnalysisContext) -> Self::AnalyzedNode {
+        let mut verb = self.verb.analyze(ctx);
+        let mut vocative = self.vocative.analyze(ctx);
+        let mut issues = Vec::new();
+        if let Resolution::Ambiguous(candidates) = &vocative.resolution {
+            issues.push(AnalysisIssue {
+                range: *vocative.get_range(),
+                message: ambiguous("vocative", &self.vocative.name, candidates),
+            });
+        }
+        if let Resolution::Ambiguous(candidates) = &verb.resolution {
+            issues.push(AnalysisIssue {
+                range: *verb.get_range(),
+                message: ambiguous("verb", self.verb.name(), candidates),
+            });
+        }
+        issues.extend(
+            verb.template
+                .as_ref()
+                .and_then(|t| t.metadata.check_parts(&self.parts).err())
+                .map(|message| AnalysisIssue {
+                    range: *verb.get_range(),
+                    message,
+                }),
+        );
+
+        // Inferred nodes have an empty range, so their neighbour shows what was filled in
+        if vocative.node.inferred {
+            verb.hover_text = format!("{}\n\n---\n\n{}", vocative.hover_text, verb.hover_text);
+        }
+        if verb.node.is_inferred() {
+            vocative.hover_text = format!("{}\n\n---\n\n{}", vocative.hover_text, verb.hover_text);
+        }
+
         AnalyzedSentence {
             node: self.clone(),
             hover_text: "This is a part".to_string(),
-            verb: self.verb.analyze(_ctx),
-            vocative: self.vocative.analyze(_ctx),
-            parts: self.parts.iter().map(|part| part.analyze(_ctx)).collect(),
+            verb,
+            vocative,
+            parts: self.parts.iter().map(|part| part.analyze(ctx)).collect(),
+            issues,
+            hints: mistyped_verb(self).into_iter().collect(),
         }
     }
 }
@@ -1,6 +1,109 @@
+use std::collections::BTreeSet;
+
 use lsp_types::Range;
+use serde::Serialize;
+
+use crate::{error::LakonikError, templates::create_user_template};
+
+/// State shared by the nodes of a sentence while it is analyzed
+#[derive(Debug, Default)]
+pub struct AnalysisContext {
+    /// Changes the sentence asks for, which analysis itself never applies
+    pub pending_writes: Vec<PendingWrite>,
+    /// Whether a fixture evaluates the sentence, so that what an assignment renders does not
+    /// depend on the templates saved so far
+    pub fixture: bool,
+}
+
+/// A change to the user's configuration that is only made once a sentence is evaluated. Verb
+/// assignments are the only statements that write anything so far, so there are no pending
+/// variable writes until sentences can assign variables.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PendingWrite {
+    /// Saves a user template, replacing any existing one
+    Template { path: String, contents: String },
+}
+
+impl PendingWrite {
+    pub fn commit(&self) -> Result<(), LakonikError> {
+        match self {
+            PendingWrite::Template { path, contents } => create_user_template(path, contents),
+        }
+    }
+}
+
+/// A problem found during analysis that keeps the sentence from being evaluated
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnalysisIssue {
+    pub range: Range,
+    pub message: String,
+}
+
+/// How a name typed in a sentence was matched to a known name
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    /// Typed in full
+    Exact,
+    /// One of the aliases declared in front-matter
+    Alias,
+    /// The only name or alias that starts with what was typed
+    Prefix,
+    /// Several names or aliases start with what was typed; holds their names
+    Ambiguous(Vec<String>),
+    Unknown,
+}
+
+impl Resolution {
+    /// Explains how `typed` became `name`, for hover texts
+    pub fn describe(&self, typed: &str, name: &str) -> Option<String> {
+        match self {
+            Resolution::Exact | Resolution::Unknown => None,
+            Resolution::Alias => Some(format!("`{typed}` is an alias of **{name}**")),
+            Resolution::Prefix => Some(format!("`{typed}` is short for **{name}**")),
+            Resolution::Ambiguous(candidates) => Some(format!(
+                "`{typed}` is ambiguous, it could be `{}`",
+                candidates.join("`, `")
+            )),
+        }
+    }
+}
 
-pub struct AnalysisContext {}
+/// A name and its aliases, as the pairs that `resolve_name` matches against
+pub fn name_with_aliases(name: &str, aliases: &[String]) -> Vec<(String, String)> {
+    std::iter::once(name)
+        .chain(aliases.iter().map(String::as_str))
+        .map(|key| (key.to_string(), name.to_string()))
+        .collect()
+}
+
+/// Matches a typed name vim-style against `(name or alias, name)` pairs: a full name first, then
+/// an alias, then the start of a name or alias that only one name has. Returns the name.
+pub fn resolve_name<'a>(
+    typed: &str,
+    known: &'a [(String, String)],
+) -> (Option<&'a str>, Resolution) {
+    if let Some((_, name)) = known.iter().find(|(key, name)| key == typed && key == name) {
+        return (Some(name), Resolution::Exact);
+    }
+    if let Some((_, name)) = known.iter().find(|(key, _)| key == typed) {
+        return (Some(name), Resolution::Alias);
+    }
+
+    let candidates = known
+        .iter()
+        .filter(|(key, _)| key.starts_with(typed))
+        .map(|(_, name)| name.as_str())
+        .collect::<BTreeSet<_>>();
+    match candidates.len() {
+        0 => (None, Resolution::Unknown),
+        1 => (candidates.first().copied(), Resolution::Prefix),
+        _ => (
+            None,
+            Resolution::Ambiguous(candidates.into_iter().map(str::to_string).collect()),
+        ),
+    }
+}
 
 pub trait Analyzable {
     type AnalyzedNode;
@@ -11,3 +114,38 @@ pub trait Analyzable {
 pub trait Analyzed {
     fn get_range(&self) -> &Range;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn known() -> Vec<(String, String)> {
+        [
+            ("create", "create"),
+            ("crop", "crop"),
+            ("mk", "create"),
+            ("review", "review"),
+            ("rv", "review"),
+        ]
+        .into_iter()
+        .map(|(key, name)| (key.to_string(), name.to_string()))
+        .collect()
+    }
+
+    #[rstest]
+    #[case("create", Some("create"), Resolution::Exact)]
+    #[case("mk", Some("create"), Resolution::Alias)]
+    #[case("cre", Some("create"), Resolution::Prefix)]
+    #[case("m", Some("create"), Resolution::Prefix)]
+    #[case("r", Some("review"), Resolution::Prefix)]
+    #[case("cr", None, Resolution::Ambiguous(vec!["create".to_string(), "crop".to_string()]))]
+    #[case("x", None, Resolution::Unknown)]
+    fn names_resolve_by_name_alias_or_unique_prefix(
+        #[case] typed: &str,
+        #[case] name: Option<&str>,
+        #[case] resolution: Resolution,
+    ) {
+        assert_eq!(resolve_name(typed, &known()), (name, resolution));
+    }
+}
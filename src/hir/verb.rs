@@ -1,61 +1,174 @@
 use lsp_types::Range;
 
 use crate::{
-    ast::Verb,
-    templates::{get_all_templates, get_user_templates},
+    ast::{AssignmentMode, Verb, VerbAssignment},
+    templates::{Template, assignment_contents, find_verb_template, get_all_templates},
 };
 
-use super::utils::{AnalysisContext, Analyzable, Analyzed};
+use super::utils::{
+    AnalysisContext, Analyzable, Analyzed, PendingWrite, Resolution, name_with_aliases,
+    resolve_name,
+};
 
 #[derive(Debug, Clone)]
 pub struct AnalyzedVerb {
     pub node: Verb,
+    /// The verb's full name, which may differ from what was typed
+    pub name: String,
+    pub resolution: Resolution,
     pub template_name: String,
+    pub template: Option<Template>,
+    /// Template defined by an assignment, which takes the place of `template` when rendering
+    pub definition: Option<String>,
     pub hover_text: String,
 }
 
 impl AnalyzedVerb {
-    /// Creates a template if it doeds not exist but can be created
-    pub fn ensure_template(&self) {
-        match &self.node {
-            Verb::Simple(_) => (),
-            Verb::Assignment(node) => {
-                let template_name = format!("verbs/{}", node.name);
+    pub fn is_assignment(&self) -> bool {
+        matches!(self.node, Verb::Assignment(_))
+    }
+}
 
-                if !get_user_templates().any(|t| t.path == template_name) {
-                    crate::templates::create_user_template(&template_name, &node.value);
-                }
-            }
-        }
+fn known_verbs(templates: &[Template]) -> Vec<(String, String)> {
+    templates
+        .iter()
+        .rev()
+        .flat_map(|t| name_with_aliases(t.verb_name().unwrap_or_default(), &t.metadata.aliases))
+        .collect()
+}
+
+/// Finds the template of a verb by its name, an alias, or the start of either that only one verb
+/// has. User templates take precedence over built-ins.
+pub fn resolve_verb(typed: &str) -> (Option<Template>, Resolution) {
+    let templates = get_all_templates()
+        .filter(|t| t.verb_name().is_some())
+        .collect::<Vec<_>>();
+    let known = known_verbs(&templates);
+
+    let (name, resolution) = resolve_name(typed, &known);
+    let template = name.and_then(|name| {
+        templates
+            .iter()
+            .rev()
+            .find(|t| t.verb_name() == Some(name))
+            .cloned()
+    });
+
+    (template, resolution)
+}
+
+/// The verb that a word which is no verb was probably meant to be: one typo away from a name or
+/// an alias, or two for longer words
+pub fn misspelled_verb(word: &str) -> Option<String> {
+    let max_distance = match word.chars().count() {
+        0..3 => return None,
+        3..6 => 1,
+        _ => 2,
+    };
+    let templates = get_all_templates()
+        .filter(|t| t.verb_name().is_some())
+        .collect::<Vec<_>>();
+    let known = known_verbs(&templates);
+    if resolve_name(word, &known).1 != Resolution::Unknown {
+        return None;
+    }
+
+    known
+        .into_iter()
+        .map(|(key, name)| (strsim::osa_distance(word, &key), name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// The template an assignment renders with, unless a plain `=` finds a verb of that name already
+/// exists in any layer. Fixtures always render the assignment.
+fn definition(node: &VerbAssignment, fixture: bool) -> Option<String> {
+    let exists = || {
+        !fixture
+            && find_verb_template(&node.name)
+                .is_some_and(|t| t.verb_name() == Some(node.name.as_str()))
+    };
+
+    match node.mode {
+        AssignmentMode::Define if exists() => None,
+        _ => Some(assignment_contents(&node.value)),
     }
 }
 
 impl Analyzable for Verb {
     type AnalyzedNode = AnalyzedVerb;
 
-    fn analyze(&self, _ctx: &mut AnalysisContext) -> Self::AnalyzedNode {
-        let template_name = match self {
-            Verb::Simple(node) => node.name.clone(),
-            Verb::Assignment(node) => node.name.clone(),
+    fn analyze(&self, ctx: &mut AnalysisContext) -> Self::AnalyzedNode {
+        let (template, resolution) = match self {
+            Verb::Simple(node) => resolve_verb(&node.name),
+            // Ephemeral templates are never saved, so there is nothing to look up
+            Verb::Assignment(node) if node.mode == AssignmentMode::Ephemeral => {
+                (None, Resolution::Exact)
+            }
+            // Assignments always define the template under their own name, never an alias
+            Verb::Assignment(node) => (
+                find_verb_template(&node.name)
+                    .filter(|t| t.verb_name() == Some(node.name.as_str())),
+                Resolution::Exact,
+            ),
         };
-        let template_name = format!("verbs/{}", template_name);
-        let mut template_source = get_all_templates()
-            .find(|t| t.path == template_name)
-            .map(|t| t.contents.clone())
+        let name = template
+            .as_ref()
+            .and_then(|t| t.verb_name())
+            .unwrap_or(self.name())
+            .to_string();
+        let template_name = template
+            .as_ref()
+            .map(|t| t.path.clone())
+            .unwrap_or_else(|| format!("verbs/{}", self.name()));
+        let mut template_source = template
+            .as_ref()
+            .map(|t| t.body().to_string())
             .unwrap_or_else(|| "*N/A*".to_string());
 
         if let Verb::Assignment(node) = self {
             template_source = node.value.clone();
         }
 
-        let hover_text = format!(
-            "_Verb_ **{}**\n\n```\n{}\n```",
-            template_name, template_source
-        );
+        let definition = match self {
+            Verb::Simple(_) => None,
+            Verb::Assignment(node) => definition(node, ctx.fixture),
+        };
+        if let (Verb::Assignment(node), Some(contents)) = (self, &definition)
+            && node.mode != AssignmentMode::Ephemeral
+        {
+            ctx.pending_writes.push(PendingWrite::Template {
+                path: format!("verbs/{}", node.name),
+                contents: contents.clone(),
+            });
+        }
+
+        let mut hover_text = format!("_Verb_ **{template_name}**");
+        if self.is_inferred() {
+            hover_text.push_str(" (inferred: the sentence names no verb)");
+        }
+        if let Some(resolved) = resolution.describe(self.name(), &name) {
+            hover_text.push_str(&format!("\n\n{resolved}"));
+        }
+        if let Some(template) = &template {
+            if let Some(description) = template.description() {
+                hover_text.push_str(&format!("\n\n{description}"));
+            }
+            if !template.metadata.aliases.is_empty() {
+                let aliases = template.metadata.aliases.join("`, `");
+                hover_text.push_str(&format!("\n\nAliases: `{aliases}`"));
+            }
+        }
+        hover_text.push_str(&format!("\n\n```\n{template_source}\n```"));
 
         AnalyzedVerb {
             node: self.clone(),
+            name,
+            resolution,
             template_name,
+            template,
+            definition,
             hover_text,
         }
     }
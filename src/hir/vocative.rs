@@ -1,25 +1,85 @@
 use lsp_types::Range;
 
 use crate::ast::Vocative;
+use crate::templates::{Template, TemplateType, find_vocative_template, get_all_templates};
 
 use super::{
     part::AnalyzedPart,
-    utils::{AnalysisContext, Analyzable, Analyzed},
+    utils::{AnalysisContext, Analyzable, Analyzed, Resolution, name_with_aliases, resolve_name},
 };
 
 #[derive(Clone, Debug)]
 pub struct AnalyzedVocative {
     pub node: Vocative,
+    /// The vocative's full name, which may differ from what was typed
+    pub name: String,
+    pub resolution: Resolution,
+    /// Template of the system prompt, either the vocative's own or the shared default
+    pub template: Option<Template>,
     pub hover_text: String,
 }
 
+fn known_vocatives() -> Vec<(String, String)> {
+    get_all_templates()
+        .filter(|t| t.template_type == TemplateType::Vocative)
+        .flat_map(|t| {
+            let name = t.path.trim_start_matches("vocatives/");
+            name_with_aliases(name, &t.metadata.aliases)
+        })
+        .collect()
+}
+
+/// Whether `name` is the full name or an alias of a vocative template
+pub fn is_vocative_name(name: &str) -> bool {
+    matches!(
+        resolve_name(name, &known_vocatives()).1,
+        Resolution::Exact | Resolution::Alias
+    )
+}
+
+/// Finds the name of a vocative among the vocative templates, by its name, an alias, or the start
+/// of either that only one vocative has. Any other name is a vocative without a template of its
+/// own, and is kept as typed.
+pub fn resolve_vocative(typed: &str) -> (String, Resolution) {
+    let known = known_vocatives();
+    let (name, resolution) = resolve_name(typed, &known);
+    match (name, resolution) {
+        (Some(name), resolution) => (name.to_string(), resolution),
+        (None, Resolution::Unknown) => (typed.to_string(), Resolution::Exact),
+        (None, resolution) => (typed.to_string(), resolution),
+    }
+}
+
 impl Analyzable for Vocative {
     type AnalyzedNode = AnalyzedVocative;
 
     fn analyze(&self, _ctx: &mut AnalysisContext) -> Self::AnalyzedNode {
+        let (name, resolution) = resolve_vocative(&self.name);
+        let template = find_vocative_template(&name);
+        let mut hover_text = format!("_Vocative_ **{name}**");
+        if self.inferred {
+            hover_text.push_str(" (inferred: the sentence starts with its verb)");
+        }
+        if let Some(resolved) = resolution.describe(&self.name, &name) {
+            hover_text.push_str(&format!("\n\n{resolved}"));
+        }
+        if let Some(template) = &template {
+            if let Some(description) = template.description() {
+                hover_text.push_str(&format!("\n\n{description}"));
+            }
+            hover_text.push_str(&format!(
+                "\n\nSystem prompt from `{}`:\n\n```\n{}\n```",
+                template.path,
+                template.body()
+            ));
+        }
+
         AnalyzedVocative {
             node: self.clone(),
-            hover_text: format!("Hover text for vocative: {}", self.name),
+            name,
+            resolution,
+            template,
+            hover_text,
         }
     }
 }
@@ -30,6 +90,7 @@ impl Analyzed for AnalyzedPart {
             AnalyzedPart::Freeform(part) => &part.node.range,
             AnalyzedPart::FilePath(part) => &part.node.range,
             AnalyzedPart::InlineShell(part) => &part.node.range,
+            AnalyzedPart::Stdin(part) => &part.node.range,
         }
     }
 }
@@ -39,3 +100,22 @@ impl Analyzed for AnalyzedVocative {
         &self.node.range
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serial_test::serial;
+
+    #[rstest]
+    #[case("qwen3", Resolution::Exact)]
+    #[case("q3", Resolution::Alias)]
+    #[case("qw", Resolution::Prefix)]
+    #[serial(lakonik_config)]
+    fn vocatives_resolve_by_name_alias_or_unique_prefix(
+        #[case] typed: &str,
+        #[case] resolution: Resolution,
+    ) {
+        assert_eq!(resolve_vocative(typed), ("qwen3".to_string(), resolution));
+    }
+}
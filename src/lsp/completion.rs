@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use lsp_types::{CompletionItem, CompletionItemKind, Position};
+
+use crate::templates::{Template, get_all_templates};
+
+/// Whether the cursor is on the second word of the sentence, where the verb goes. This works on
+/// the raw text, since a sentence that is still being typed usually does not parse.
+fn in_verb_position(text: &str, pos: &Position) -> bool {
+    let mut prefix = text
+        .lines()
+        .take(pos.line as usize)
+        .map(|line| format!("{line}\n"))
+        .collect::<String>();
+    if let Some(line) = text.lines().nth(pos.line as usize) {
+        prefix.extend(line.chars().take(pos.character as usize));
+    }
+
+    let words = prefix.split_whitespace().count();
+    let after_space = prefix.ends_with(char::is_whitespace);
+
+    (words == 1 && after_space) || (words == 2 && !after_space)
+}
+
+fn verb_item(name: &str, template: &Template) -> CompletionItem {
+    CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: template.description(),
+        ..CompletionItem::default()
+    }
+}
+
+fn alias_item(alias: &str, name: &str) -> CompletionItem {
+    CompletionItem {
+        label: alias.to_string(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: Some(format!("Alias of `{name}`")),
+        ..CompletionItem::default()
+    }
+}
+
+/// Suggests every invocable verb, along with its aliases, while the verb is being typed
+pub fn completions(text: &str, pos: &Position) -> Option<Vec<CompletionItem>> {
+    if !in_verb_position(text, pos) {
+        return None;
+    }
+
+    // Later templates override earlier ones of the same name
+    let verbs = get_all_templates()
+        .filter_map(|t| Some((t.verb_name()?.to_string(), t)))
+        .collect::<BTreeMap<_, _>>();
+
+    let items = verbs
+        .iter()
+        .flat_map(|(name, template)| {
+            std::iter::once(verb_item(name, template)).chain(
+                template
+                    .metadata
+                    .aliases
+                    .iter()
+                    .map(|alias| alias_item(alias, name)),
+            )
+        })
+        .collect();
+
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serial_test::serial;
+
+    #[rstest]
+    #[case("qwen3 ", 0, 6, true)]
+    #[case("qwen3 cre", 0, 9, true)]
+    #[case("  qwen3\n  cr", 1, 4, true)]
+    #[case("qwen3", 0, 5, false)]
+    #[case("qwen3 create ", 0, 13, false)]
+    #[case("qwen3 create foo", 0, 8, true)]
+    fn verb_position(
+        #[case] text: &str,
+        #[case] line: u32,
+        #[case] character: u32,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(
+            in_verb_position(text, &Position::new(line, character)),
+            expected
+        );
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn suggests_built_in_verbs_with_description() {
+        let items = completions("qwen3 ", &Position::new(0, 6)).unwrap();
+        let create = items.iter().find(|i| i.label == "create").unwrap();
+
+        assert_eq!(
+            create.detail.as_deref(),
+            Some("Ask for something to be created")
+        );
+        assert!(items.iter().all(|i| i.label != "base/base"));
+    }
+}
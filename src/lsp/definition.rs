@@ -0,0 +1,73 @@
+use lsp_types::{Location, Position, Range, TextDocumentIdentifier, Url, request::Request};
+
+use crate::ast::utils::RangeContainsPosition;
+use crate::hir::part::AnalyzedPart;
+use crate::hir::sentence::AnalyzedSentence;
+use crate::hir::utils::Analyzed;
+use crate::templates::{Template, TemplateSource, get_built_in_template};
+
+use super::preview::workspace_path;
+
+/// URI scheme for the read-only templates embedded into the binary
+pub const BUILT_IN_SCHEME: &str = "lakonik-builtin";
+
+/// Custom request that lets clients load the contents of a `lakonik-builtin://` URI
+pub enum BuiltInTemplateContents {}
+
+impl Request for BuiltInTemplateContents {
+    type Params = TextDocumentIdentifier;
+    type Result = Option<String>;
+    const METHOD: &'static str = "lakonik/builtInTemplateContents";
+}
+
+pub fn template_uri(template: &Template) -> Option<Url> {
+    match template.source {
+        TemplateSource::BuiltIn => {
+            Url::parse(&format!("{BUILT_IN_SCHEME}:///{}", template.path)).ok()
+        }
+        TemplateSource::User | TemplateSource::Project => template
+            .file_path()
+            .and_then(|path| Url::from_file_path(path).ok()),
+    }
+}
+
+pub fn built_in_template_contents(uri: &Url) -> Option<String> {
+    if uri.scheme() != BUILT_IN_SCHEME {
+        return None;
+    }
+
+    get_built_in_template(uri.path().trim_start_matches('/')).map(|t| t.contents)
+}
+
+fn file_uri(path: &str) -> Option<Url> {
+    Url::from_file_path(workspace_path(path)).ok()
+}
+
+pub fn find_definition(analyzed: &AnalyzedSentence, pos: &Position) -> Option<Location> {
+    let start_of_file = Range::default();
+
+    if analyzed.vocative.get_range().contains_position(pos) {
+        return analyzed
+            .vocative
+            .template
+            .as_ref()
+            .and_then(template_uri)
+            .map(|uri| Location::new(uri, start_of_file));
+    }
+
+    if analyzed.verb.get_range().contains_position(pos) {
+        return analyzed
+            .verb
+            .template
+            .as_ref()
+            .and_then(template_uri)
+            .map(|uri| Location::new(uri, start_of_file));
+    }
+
+    analyzed.parts.iter().find_map(|part| match part {
+        AnalyzedPart::FilePath(p) if p.node.range.contains_position(pos) => {
+            file_uri(&p.node.path).map(|uri| Location::new(uri, start_of_file))
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,150 @@
+use lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
+
+use crate::hir::sentence::AnalyzedSentence;
+use crate::templates::check::{Severity, check_template};
+use crate::templates::fixtures::is_fixture;
+use crate::templates::manage::list_templates;
+use crate::templates::{Template, TemplateSource, find_project_template_dir, user_template_dir};
+
+/// The template an open file holds, if it lies in a project or user template directory. The
+/// text of the editor is used rather than what is saved.
+pub fn template_for(uri: &Url, text: &str) -> Option<Template> {
+    let file_path = uri.to_file_path().ok()?;
+    let layers = [
+        (
+            TemplateSource::Project,
+            find_project_template_dir(file_path.parent()?),
+        ),
+        (TemplateSource::User, user_template_dir()),
+    ];
+
+    layers.into_iter().find_map(|(source, root)| {
+        let path = file_path.strip_prefix(root?).ok()?;
+        if is_fixture(&path.to_string_lossy()) {
+            return None;
+        }
+
+        Some(Template::new(
+            path.to_string_lossy().replace('\\', "/"),
+            text.to_string(),
+            source,
+        ))
+    })
+}
+
+/// The same diagnostics as `lakonik templates check`, each covering its whole line
+pub fn template_diagnostics(template: &Template) -> Vec<Diagnostic> {
+    let lines = template.contents.lines().collect::<Vec<_>>();
+
+    check_template(template, &list_templates())
+        .into_iter()
+        .map(|diagnostic| {
+            let line = diagnostic.line.saturating_sub(1);
+            let length = lines.get(line).map_or(0, |l| l.chars().count());
+
+            Diagnostic {
+                range: Range::new(
+                    Position::new(line as u32, 0),
+                    Position::new(line as u32, length as u32),
+                ),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("lakonik".to_string()),
+                message: diagnostic.message,
+                ..Diagnostic::default()
+            }
+        })
+        .collect()
+}
+
+/// The issues that keep a sentence from being evaluated, such as an ambiguous abbreviation, and
+/// hints at likely mistakes, such as a mistyped verb
+pub fn sentence_diagnostics(analyzed: &AnalyzedSentence) -> Vec<Diagnostic> {
+    let issues = analyzed
+        .issues
+        .iter()
+        .map(|issue| (issue, DiagnosticSeverity::ERROR));
+    let hints = analyzed
+        .hints
+        .iter()
+        .map(|hint| (hint, DiagnosticSeverity::HINT));
+
+    issues
+        .chain(hints)
+        .map(|(issue, severity)| Diagnostic {
+            range: issue.range,
+            severity: Some(severity),
+            source: Some("lakonik".to_string()),
+            message: issue.message.clone(),
+            ..Diagnostic::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Defaults, parse};
+    use crate::hir::utils::{AnalysisContext, Analyzable};
+    use rstest::rstest;
+    use serial_test::serial;
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn project_template_files_are_recognized() {
+        let project = tempfile::tempdir().unwrap();
+        let verbs = project.path().join(".lakonik/templates/verbs");
+        std::fs::create_dir_all(&verbs).unwrap();
+        let uri = Url::from_file_path(verbs.join("review")).unwrap();
+
+        let template = template_for(&uri, "review {{ description }}").unwrap();
+        assert_eq!(template.path, "verbs/review");
+        assert_eq!(template.source, TemplateSource::Project);
+
+        let outside = Url::from_file_path(project.path().join("notes.lk")).unwrap();
+        assert!(template_for(&outside, "").is_none());
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn diagnostics_cover_the_offending_line() {
+        let template = Template::new(
+            "verbs/review".to_string(),
+            "review\n{{ descripton }}".to_string(),
+            TemplateSource::Project,
+        );
+
+        let diagnostics = template_diagnostics(&template);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].range,
+            Range::new(Position::new(1, 0), Position::new(1, 16))
+        );
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+    }
+
+    #[rstest]
+    #[case("robot craete a parser", Some((6, 12)))]
+    #[case("robot create a parser", None)]
+    #[case("robot the parser", None)]
+    #[case("robot cr a parser", None)]
+    fn mistyped_verbs_are_hinted_at(#[case] input: &str, #[case] hinted: Option<(u32, u32)>) {
+        let defaults = Defaults {
+            vocative: None,
+            verb: Some("create".to_string()),
+        };
+        let analyzed = parse(input, &defaults)
+            .unwrap()
+            .analyze(&mut AnalysisContext::default());
+
+        let hints = sentence_diagnostics(&analyzed)
+            .into_iter()
+            .filter(|d| d.severity == Some(DiagnosticSeverity::HINT))
+            .map(|d| (d.range.start.character, d.range.end.character))
+            .collect::<Vec<_>>();
+        assert_eq!(hints, hinted.into_iter().collect::<Vec<_>>());
+    }
+}
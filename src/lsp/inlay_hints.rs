@@ -0,0 +1,118 @@
+use lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, Position, Range};
+
+use crate::hir::part::AnalyzedPart;
+use crate::hir::sentence::AnalyzedSentence;
+use crate::templates::functions::BYTES_PER_TOKEN;
+
+use super::preview::{ShellPreviewCache, format_size, shell_output, workspace_path};
+
+pub fn estimate_tokens(bytes: u64) -> u64 {
+    bytes.div_ceil(BYTES_PER_TOKEN as u64)
+}
+
+fn format_tokens(tokens: u64) -> String {
+    if tokens < 1000 {
+        format!("≈{tokens} tokens")
+    } else {
+        format!("≈{:.1}k tokens", tokens as f64 / 1000.0)
+    }
+}
+
+fn hint(position: Position, label: String) -> InlayHint {
+    InlayHint {
+        position,
+        label: InlayHintLabel::String(label),
+        kind: Some(InlayHintKind::TYPE),
+        text_edits: None,
+        tooltip: None,
+        padding_left: Some(true),
+        padding_right: None,
+        data: None,
+    }
+}
+
+fn is_visible(position: &Position, range: &Range) -> bool {
+    range.start <= *position && *position <= range.end
+}
+
+/// Shows how big each shell output and attached file will be once the sentence is expanded
+pub async fn inlay_hints(
+    analyzed: &AnalyzedSentence,
+    range: &Range,
+    cache: &ShellPreviewCache,
+) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+
+    for part in &analyzed.parts {
+        match part {
+            AnalyzedPart::FilePath(p) if is_visible(&p.node.range.end, range) => {
+                if let Ok(metadata) = tokio::fs::metadata(workspace_path(&p.node.path)).await {
+                    let label = format_tokens(estimate_tokens(metadata.len()));
+                    hints.push(hint(p.node.range.end, label));
+                }
+            }
+            AnalyzedPart::InlineShell(p) if p.node.interpreter.is_none() => {
+                // The range of the code excludes the closing parenthesis
+                let mut position = p.node.range.end;
+                position.character += 1;
+
+                if !is_visible(&position, range) {
+                    continue;
+                }
+                if let Some(Ok(output)) = shell_output(&p.node.code, cache).await {
+                    let lines = output.lines().count();
+                    let label = format!(
+                        "{lines} {}, {}",
+                        if lines == 1 { "line" } else { "lines" },
+                        format_size(output.len() as u64)
+                    );
+                    hints.push(hint(position, label));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Span, parse_statement};
+    use crate::hir::utils::{AnalysisContext, Analyzable};
+    use rstest::rstest;
+    use serial_test::serial;
+
+    #[rstest]
+    #[case(0, "≈0 tokens")]
+    #[case(10, "≈3 tokens")]
+    #[case(3996, "≈999 tokens")]
+    #[case(6000, "≈1.5k tokens")]
+    fn token_estimates(#[case] bytes: u64, #[case] expected: &str) {
+        assert_eq!(format_tokens(estimate_tokens(bytes)), expected);
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn hints_follow_shell_and_file_parts() {
+        let text = "robot create $(echo hi) @Cargo.toml @missing.txt $(touch x)";
+        let (_, ast) = parse_statement(Span::new(text)).unwrap();
+        let analyzed = ast.analyze(&mut AnalysisContext::default());
+        let everything = Range::new(Position::new(0, 0), Position::new(0, text.len() as u32));
+
+        let hints = inlay_hints(&analyzed, &everything, &ShellPreviewCache::default()).await;
+        let hints = hints
+            .into_iter()
+            .map(|h| match h.label {
+                InlayHintLabel::String(label) => (h.position.character, label),
+                InlayHintLabel::LabelParts(_) => panic!("unexpected label parts"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(hints.len(), 2, "{hints:?}");
+        assert_eq!(hints[0], (23, "1 line, 3 B".to_string()));
+        assert_eq!(hints[1].0, 35);
+        assert!(hints[1].1.ends_with(" tokens"));
+    }
+}
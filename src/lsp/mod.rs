@@ -1,8 +1,21 @@
+mod completion;
+mod definition;
+mod diagnostics;
+mod inlay_hints;
+mod preview;
+mod rename;
+mod semantic_tokens;
+mod signature_help;
+mod symbols;
 mod utils;
+mod workspace;
 use std::collections::HashMap;
 use std::ops::ControlFlow;
+use std::path::PathBuf;
 
 use crate::ast::utils::RangeContainsPosition;
+use crate::config::{Config, Settings, set_project_root};
+use crate::engine::Defaults;
 use crate::hir::part::AnalyzedPart;
 use crate::hir::sentence::AnalyzedSentence;
 use crate::hir::utils::Analyzed;
@@ -13,26 +26,52 @@ use async_lsp::router::Router;
 use async_lsp::server::LifecycleLayer;
 use async_lsp::tracing::TracingLayer;
 use async_lsp::{ClientSocket, LanguageServer, ResponseError};
+pub use definition::BUILT_IN_SCHEME;
+use definition::{BuiltInTemplateContents, built_in_template_contents, find_definition};
 use futures::future::BoxFuture;
 use lsp_types::{
-    DidChangeConfigurationParams, Hover, HoverContents, HoverParams, HoverProviderCapability,
-    InitializeParams, InitializeResult, MarkedString, Position, ServerCapabilities,
-    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+    CompletionOptions, CompletionParams, CompletionResponse, Diagnostic,
+    DidChangeConfigurationParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeParams, InitializeResult,
+    InlayHint, InlayHintParams, MarkedString, OneOf, Position, PrepareRenameResponse,
+    PublishDiagnosticsParams, RenameOptions, RenameParams, SemanticTokens,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelp, SignatureHelpOptions,
+    SignatureHelpParams, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url, WorkspaceEdit, WorkspaceSymbolParams, WorkspaceSymbolResponse,
     notification::{
         DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+        PublishDiagnostics,
     },
 };
+use preview::{ShellPreviewCache, find_preview_target, render_preview};
 use tower::ServiceBuilder;
 use tracing::Level;
 use utils::update_document;
+use workspace::workspace_documents;
 
 pub struct DocumentState {
-    analyzed: AnalyzedSentence,
+    /// `None` while the text does not parse, e.g. while a sentence is being typed
+    analyzed: Option<AnalyzedSentence>,
+    text: String,
 }
 
 pub struct ServerState {
-    _client: ClientSocket,
+    client: ClientSocket,
     docs: HashMap<Url, DocumentState>,
+    workspace_roots: Vec<PathBuf>,
+    shell_previews: ShellPreviewCache,
+    settings: Settings,
+}
+
+fn load_settings() -> Settings {
+    Config::load()
+        .map(|config| config.settings)
+        .unwrap_or_else(|err| {
+            tracing::warn!("Could not load the configuration: {err}");
+            Settings::default()
+        })
 }
 
 impl LanguageServer for ServerState {
@@ -44,6 +83,13 @@ impl LanguageServer for ServerState {
         params: InitializeParams,
     ) -> BoxFuture<'static, Result<InitializeResult, Self::Error>> {
         eprintln!("Initialize with {params:?}");
+        self.workspace_roots = workspace::workspace_roots(&params);
+        // Project settings and templates belong to the workspace, not wherever the editor was
+        // started from
+        if let Some(root) = self.workspace_roots.first() {
+            set_project_root(Some(root.clone()));
+            self.settings = load_settings();
+        }
         Box::pin(async move {
             Ok(InitializeResult {
                 capabilities: ServerCapabilities {
@@ -51,6 +97,29 @@ impl LanguageServer for ServerState {
                         TextDocumentSyncKind::FULL,
                     )),
                     hover_provider: Some(HoverProviderCapability::Simple(true)),
+                    completion_provider: Some(CompletionOptions::default()),
+                    definition_provider: Some(OneOf::Left(true)),
+                    rename_provider: Some(OneOf::Right(RenameOptions {
+                        prepare_provider: Some(true),
+                        work_done_progress_options: Default::default(),
+                    })),
+                    workspace_symbol_provider: Some(OneOf::Left(true)),
+                    inlay_hint_provider: Some(OneOf::Left(true)),
+                    signature_help_provider: Some(SignatureHelpOptions {
+                        trigger_characters: Some(vec![" ".to_string()]),
+                        retrigger_characters: None,
+                        work_done_progress_options: Default::default(),
+                    }),
+                    semantic_tokens_provider: Some(
+                        SemanticTokensServerCapabilities::SemanticTokensOptions(
+                            SemanticTokensOptions {
+                                legend: semantic_tokens::legend(),
+                                range: Some(true),
+                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                ..SemanticTokensOptions::default()
+                            },
+                        ),
+                    ),
                     ..ServerCapabilities::default()
                 },
                 server_info: None,
@@ -68,17 +137,170 @@ impl LanguageServer for ServerState {
             .uri
             .clone();
         let pos = params.text_document_position_params.position;
-        let analyzed_opt = self.docs.get(&uri).map(|doc| doc.analyzed.clone());
+        let analyzed_opt = self.analyzed(&uri).cloned();
+        let shell_previews = self.shell_previews();
+
+        Box::pin(async move {
+            let Some(analyzed) = analyzed_opt else {
+                return Ok(None);
+            };
+            let Some(mut text) = find_hover_text(&analyzed, &pos).map(str::to_string) else {
+                return Ok(None);
+            };
+
+            if let Some(target) = find_preview_target(&analyzed, &pos) {
+                text.push_str("\n\n");
+                text.push_str(&render_preview(target, shell_previews).await);
+            }
+
+            Ok(Some(Hover {
+                contents: HoverContents::Scalar(MarkedString::String(text)),
+                range: None,
+            }))
+        })
+    }
+
+    fn definition(
+        &mut self,
+        params: GotoDefinitionParams,
+    ) -> BoxFuture<'static, Result<Option<GotoDefinitionResponse>, Self::Error>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let pos = params.text_document_position_params.position;
+        let analyzed_opt = self.analyzed(&uri).cloned();
+
+        Box::pin(async move {
+            let location = analyzed_opt.and_then(|analyzed| find_definition(&analyzed, &pos));
+
+            Ok(location.map(GotoDefinitionResponse::Scalar))
+        })
+    }
+
+    fn prepare_rename(
+        &mut self,
+        params: TextDocumentPositionParams,
+    ) -> BoxFuture<'static, Result<Option<PrepareRenameResponse>, Self::Error>> {
+        let result = match self.analyzed(&params.text_document.uri) {
+            Some(analyzed) => rename::prepare_rename(analyzed, &params.position),
+            None => Ok(None),
+        };
+
+        Box::pin(async move { result })
+    }
+
+    fn rename(
+        &mut self,
+        params: RenameParams,
+    ) -> BoxFuture<'static, Result<Option<WorkspaceEdit>, Self::Error>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let result = match self.analyzed(&uri) {
+            Some(analyzed) => {
+                let documents =
+                    workspace_documents(&self.docs, &self.workspace_roots, &self.defaults());
+                rename::rename(analyzed, &pos, &params.new_name, &documents)
+            }
+            None => Ok(None),
+        };
+
+        Box::pin(async move { result })
+    }
+
+    fn symbol(
+        &mut self,
+        params: WorkspaceSymbolParams,
+    ) -> BoxFuture<'static, Result<Option<WorkspaceSymbolResponse>, Self::Error>> {
+        let documents = workspace_documents(&self.docs, &self.workspace_roots, &self.defaults());
+        let symbols = symbols::workspace_symbols(&params.query, &documents);
+
+        Box::pin(async move { Ok(Some(WorkspaceSymbolResponse::Nested(symbols))) })
+    }
+
+    fn inlay_hint(
+        &mut self,
+        params: InlayHintParams,
+    ) -> BoxFuture<'static, Result<Option<Vec<InlayHint>>, Self::Error>> {
+        let analyzed_opt = self.analyzed(&params.text_document.uri).cloned();
+        let shell_previews = self.shell_previews();
 
         Box::pin(async move {
-            let hover = analyzed_opt.and_then(|analyzed| {
-                find_hover_text(&analyzed, &pos).map(|txt| Hover {
-                    contents: HoverContents::Scalar(MarkedString::String(txt.to_string())),
-                    range: None,
+            let Some(analyzed) = analyzed_opt else {
+                return Ok(None);
+            };
+
+            Ok(Some(
+                inlay_hints::inlay_hints(&analyzed, &params.range, &shell_previews).await,
+            ))
+        })
+    }
+
+    fn completion(
+        &mut self,
+        params: CompletionParams,
+    ) -> BoxFuture<'static, Result<Option<CompletionResponse>, Self::Error>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let items = self
+            .docs
+            .get(&uri)
+            .and_then(|doc| completion::completions(&doc.text, &pos));
+
+        Box::pin(async move { Ok(items.map(CompletionResponse::Array)) })
+    }
+
+    fn signature_help(
+        &mut self,
+        params: SignatureHelpParams,
+    ) -> BoxFuture<'static, Result<Option<SignatureHelp>, Self::Error>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let pos = params.text_document_position_params.position;
+        let help = self
+            .analyzed(&uri)
+            .and_then(|analyzed| signature_help::signature_help(analyzed, &pos));
+
+        Box::pin(async move { Ok(help) })
+    }
+
+    fn semantic_tokens_full(
+        &mut self,
+        params: SemanticTokensParams,
+    ) -> BoxFuture<'static, Result<Option<SemanticTokensResult>, Self::Error>> {
+        let data = self.docs.get(&params.text_document.uri).and_then(|doc| {
+            Some(semantic_tokens::semantic_tokens(
+                doc.analyzed.as_ref()?,
+                &doc.text,
+                None,
+            ))
+        });
+
+        Box::pin(async move {
+            Ok(data.map(|data| {
+                SemanticTokensResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data,
                 })
-            });
+            }))
+        })
+    }
 
-            Ok(hover)
+    fn semantic_tokens_range(
+        &mut self,
+        params: SemanticTokensRangeParams,
+    ) -> BoxFuture<'static, Result<Option<SemanticTokensRangeResult>, Self::Error>> {
+        let data = self.docs.get(&params.text_document.uri).and_then(|doc| {
+            Some(semantic_tokens::semantic_tokens(
+                doc.analyzed.as_ref()?,
+                &doc.text,
+                Some(&params.range),
+            ))
+        });
+
+        Box::pin(async move {
+            Ok(data.map(|data| {
+                SemanticTokensRangeResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data,
+                })
+            }))
         })
     }
 
@@ -91,16 +313,64 @@ impl LanguageServer for ServerState {
 }
 
 impl ServerState {
+    fn analyzed(&self, uri: &Url) -> Option<&AnalyzedSentence> {
+        self.docs.get(uri).and_then(|doc| doc.analyzed.as_ref())
+    }
+
+    fn defaults(&self) -> Defaults {
+        (&self.settings).into()
+    }
+
+    /// The shared cache of shell previews, or an empty one when `cache.shell_previews` is off
+    fn shell_previews(&self) -> ShellPreviewCache {
+        if self.settings.cache.shell_previews {
+            self.shell_previews.clone()
+        } else {
+            ShellPreviewCache::default()
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: Url, diagnostics: Vec<Diagnostic>) {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        if let Err(err) = self.client.notify::<PublishDiagnostics>(params) {
+            tracing::warn!("Could not publish diagnostics: {err}");
+        }
+    }
+
+    /// Checks template files like `lakonik templates check`, and sentences for what would keep
+    /// them from being evaluated, so that mistakes show up while editing
+    fn publish_document_diagnostics(&self, uri: &Url, text: &str) {
+        let diagnostics = match diagnostics::template_for(uri, text) {
+            Some(template) => diagnostics::template_diagnostics(&template),
+            None => self
+                .analyzed(uri)
+                .map(diagnostics::sentence_diagnostics)
+                .unwrap_or_default(),
+        };
+        self.publish_diagnostics(uri.clone(), diagnostics);
+    }
+
     fn new_router(client: ClientSocket) -> Router<Self> {
         let mut router = Router::from_language_server(Self {
-            _client: client,
+            client,
             docs: HashMap::new(),
+            workspace_roots: Vec::new(),
+            shell_previews: ShellPreviewCache::default(),
+            settings: load_settings(),
         });
 
         router.notification::<DidOpenTextDocument>(Self::on_did_open);
         router.notification::<DidChangeTextDocument>(Self::on_did_change);
         router.notification::<DidCloseTextDocument>(Self::on_did_close);
         router.notification::<DidSaveTextDocument>(Self::on_did_save);
+        router.request::<BuiltInTemplateContents, _>(|_, params| {
+            let contents = built_in_template_contents(&params.uri);
+            async move { Ok(contents) }
+        });
 
         router
     }
@@ -111,7 +381,9 @@ impl ServerState {
     ) -> ControlFlow<async_lsp::Result<()>> {
         let text = params.text_document.text.clone();
         let uri = params.text_document.uri.clone();
-        update_document(&mut self.docs, uri, text);
+        let defaults = self.defaults();
+        update_document(&mut self.docs, uri.clone(), text.clone(), &defaults);
+        self.publish_document_diagnostics(&uri, &text);
         ControlFlow::Continue(())
     }
 
@@ -128,7 +400,9 @@ impl ServerState {
                 text.len(),
                 text
             );
-            update_document(&mut self.docs, uri, text);
+            let defaults = self.defaults();
+            update_document(&mut self.docs, uri.clone(), text.clone(), &defaults);
+            self.publish_document_diagnostics(&uri, &text);
         }
         ControlFlow::Continue(())
     }
@@ -137,14 +411,18 @@ impl ServerState {
         &mut self,
         params: lsp_types::DidCloseTextDocumentParams,
     ) -> ControlFlow<async_lsp::Result<()>> {
-        self.docs.remove(&params.text_document.uri);
+        let uri = params.text_document.uri;
+        self.publish_diagnostics(uri.clone(), Vec::new());
+        self.docs.remove(&uri);
         ControlFlow::Continue(())
     }
 
+    /// Saving may change what previewed commands output, so their cached output is dropped
     fn on_did_save(
         &mut self,
         _params: lsp_types::DidSaveTextDocumentParams,
     ) -> ControlFlow<async_lsp::Result<()>> {
+        self.shell_previews.lock().unwrap().clear();
         ControlFlow::Continue(())
     }
 }
@@ -200,6 +478,11 @@ fn find_hover_text<'a>(analyzed: &'a AnalyzedSentence, pos: &Position) -> Option
                     return Some(&p.hover_text);
                 }
             }
+            AnalyzedPart::Stdin(p) => {
+                if p.node.range.contains_position(pos) {
+                    return Some(&p.hover_text);
+                }
+            }
         }
     }
     None
@@ -215,12 +498,15 @@ mod tests {
         server::LifecycleLayer, tracing::TracingLayer,
     };
     use lsp_types::{
-        DidOpenTextDocumentParams, HoverContents, HoverParams, InitializeParams, InitializedParams,
-        MarkedString, Position, TextDocumentIdentifier, TextDocumentItem,
-        TextDocumentPositionParams, Url, WorkDoneProgressParams,
+        DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentChangeOperation,
+        DocumentChanges, HoverContents, HoverParams, InitializeParams, InitializedParams,
+        MarkedString, Position, RenameFile, RenameFileOptions, ResourceOp, TextDocumentEdit,
+        TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams, Url,
+        WorkDoneProgressParams,
     };
     use regex::Regex;
     use rstest::rstest;
+    use serial_test::serial;
     use tokio::io::duplex;
     use tokio::task::JoinHandle;
     use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
@@ -262,7 +548,11 @@ mod tests {
                 .unwrap();
         });
 
-        let (client_mainloop, client_socket) = MainLoop::new_client(|_server| Router::new(()));
+        let (client_mainloop, client_socket) = MainLoop::new_client(|_server| {
+            let mut router = Router::new(());
+            router.notification::<PublishDiagnostics>(|_, _| ControlFlow::Continue(()));
+            router
+        });
 
         let client_task: JoinHandle<()> = tokio::spawn(async move {
             let mut read = client_read.compat();
@@ -276,15 +566,34 @@ mod tests {
         (client_socket, server_task, client_task)
     }
 
-    pub async fn get_hover_text(source: &str) -> Option<String> {
-        let (clean, pos) = find_hover_position(source);
+    pub struct TestSession {
+        pub client: ServerSocket,
+        pub uri: Url,
+        server_handle: JoinHandle<()>,
+        client_handle: JoinHandle<()>,
+    }
+
+    impl Drop for TestSession {
+        fn drop(&mut self) {
+            self.server_handle.abort();
+            self.client_handle.abort();
+            set_project_root(None);
+        }
+    }
+
+    pub async fn open_document(text: &str) -> TestSession {
+        open_document_in(text, None).await
+    }
 
+    pub async fn open_document_in(text: &str, root: Option<&std::path::Path>) -> TestSession {
         let (mut client, server_handle, client_handle) = launch_lsp_server().await;
 
         let uri = Url::parse("file:///testfile").unwrap();
 
+        #[allow(deprecated)]
         client
             .initialize(InitializeParams {
+                root_uri: root.map(|root| Url::from_directory_path(root).unwrap()),
                 ..Default::default()
             })
             .await
@@ -297,15 +606,30 @@ mod tests {
                     uri: uri.clone(),
                     language_id: "test".into(),
                     version: 1,
-                    text: clean.clone(),
+                    text: text.to_string(),
                 },
             })
             .unwrap();
 
-        let hover = client
+        TestSession {
+            client,
+            uri,
+            server_handle,
+            client_handle,
+        }
+    }
+
+    pub async fn get_hover_text(source: &str) -> Option<String> {
+        let (clean, pos) = find_hover_position(source);
+        let mut session = open_document(&clean).await;
+
+        let hover = session
+            .client
             .hover(HoverParams {
                 text_document_position_params: TextDocumentPositionParams {
-                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                    text_document: TextDocumentIdentifier {
+                        uri: session.uri.clone(),
+                    },
                     position: pos,
                 },
                 work_done_progress_params: WorkDoneProgressParams::default(),
@@ -313,10 +637,6 @@ mod tests {
             .await
             .unwrap();
 
-        drop(client);
-        server_handle.abort();
-        client_handle.abort();
-
         hover.map(|h| match h.contents {
             HoverContents::Scalar(MarkedString::String(s)) => s,
             HoverContents::Markup(m) => m.value,
@@ -332,9 +652,40 @@ mod tests {
         })
     }
 
+    pub async fn get_definition_uri(source: &str) -> Option<Url> {
+        let (clean, pos) = find_hover_position(source);
+        let mut session = open_document(&clean).await;
+
+        let definition = session
+            .client
+            .definition(GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: session.uri.clone(),
+                    },
+                    position: pos,
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        match definition {
+            Some(GotoDefinitionResponse::Scalar(location)) => Some(location.uri),
+            _ => None,
+        }
+    }
+
     #[rstest]
-    #[case("qw***en3 create foobar", Some(r"vocative: qwen3$"))]
-    #[case("hell***o create foobar", Some(r"vocative: hello$"))]
+    #[case(
+        "qw***en3 create foobar",
+        Some(r"(?s)_Vocative_ \*\*qwen3\*\*.*`vocatives/qwen3`")
+    )]
+    #[case(
+        "hell***o create foobar",
+        Some(r"(?s)_Vocative_ \*\*hello\*\*.*`system/default`")
+    )]
     #[case("foobar *** create lorem", None)]
     #[case(
         "test c***reate foobar",
@@ -350,7 +701,24 @@ mod tests {
         "test create test module in $(tre***e .)",
         Some(r"expand to the results of `tree .`")
     )]
+    #[case(
+        "test create $(echo hel***lo)",
+        Some(r"(?s)`echo hello`.*```\nhello\n```")
+    )]
+    #[case(
+        "test create $(touch ***foo)",
+        Some(r"(?s)`touch foo`.*not marked safe")
+    )]
+    #[case(
+        "test create @Cargo***.toml",
+        Some(r"(?s)file path part.*`Cargo.toml` · .*B · modified .*\[package\]")
+    )]
+    #[case(
+        "test create @does-not-***exist",
+        Some(r"`does-not-exist` does not exist")
+    )]
     #[tokio::test]
+    #[serial(lakonik_config)]
     async fn hover_cases(#[case] raw_input: &str, #[case] expected_pat: Option<&str>) {
         let actual = get_hover_text(raw_input).await;
 
@@ -370,4 +738,371 @@ mod tests {
             }
         }
     }
+
+    #[rstest]
+    #[case("test c***reate foobar", Some("lakonik-builtin:///verbs/create"))]
+    #[case("test create @Cargo***.toml", Some("file:///*/Cargo.toml"))]
+    #[case("test create foo***bar", None)]
+    #[case("tes***t create foobar", Some("lakonik-builtin:///system/default"))]
+    #[case("qwe***n3 create foobar", Some("lakonik-builtin:///vocatives/qwen3"))]
+    #[case("test doesnotexi***st foobar", None)]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn definition_cases(#[case] raw_input: &str, #[case] expected: Option<&str>) {
+        let actual = get_definition_uri(raw_input).await;
+
+        match expected {
+            None => assert!(actual.is_none(), "expected no definition, got `{actual:?}`"),
+            Some(pattern) => {
+                let uri = actual
+                    .expect("expected a definition, got `None`")
+                    .to_string();
+                let re = Regex::new(&format!(
+                    "^{}$",
+                    regex::escape(pattern).replace(r"\*", ".*")
+                ))
+                .expect("invalid regex");
+                assert!(re.is_match(&uri), "`{uri}` does not match `{pattern}`");
+            }
+        }
+    }
+
+    #[test]
+    fn built_in_template_contents_for_virtual_uri() {
+        let uri = Url::parse("lakonik-builtin:///verbs/create").unwrap();
+        let contents = built_in_template_contents(&uri).expect("built-in template should exist");
+        assert!(contents.contains("create for me a"));
+
+        let file_uri = Url::parse("file:///verbs/create").unwrap();
+        assert!(built_in_template_contents(&file_uri).is_none());
+    }
+
+    fn position_params(session: &TestSession, position: Position) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: session.uri.clone(),
+            },
+            position,
+        }
+    }
+
+    #[rstest]
+    #[case("test c***reate foobar", Err("built-in verb"))]
+    #[case("test ~f***oo=(bar) baz", Ok(Some((6, 9))))]
+    #[case("test some***verb baz", Ok(Some((5, 13))))]
+    #[case("te***st create foobar", Ok(None))]
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn prepare_rename_cases(
+        #[case] raw_input: &str,
+        #[case] expected: Result<Option<(u32, u32)>, &str>,
+    ) {
+        let (clean, pos) = find_hover_position(raw_input);
+        let mut session = open_document(&clean).await;
+        let params = position_params(&session, pos);
+
+        let actual = session.client.prepare_rename(params).await;
+
+        match (actual, expected) {
+            (Ok(actual), Ok(expected)) => {
+                let actual = actual.map(|response| match response {
+                    PrepareRenameResponse::Range(range) => {
+                        (range.start.character, range.end.character)
+                    }
+                    other => panic!("unexpected response {other:?}"),
+                });
+                assert_eq!(actual, expected);
+            }
+            (Err(err), Err(message)) => assert!(err.to_string().contains(message), "{err}"),
+            (actual, _) => panic!("unexpected result {actual:?}"),
+        }
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn shell_previews_are_refreshed_on_save() {
+        async fn hover(session: &mut TestSession) -> String {
+            let params = HoverParams {
+                text_document_position_params: position_params(session, Position::new(0, 16)),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            };
+            match session
+                .client
+                .hover(params)
+                .await
+                .unwrap()
+                .map(|h| h.contents)
+            {
+                Some(HoverContents::Scalar(MarkedString::String(s))) => s,
+                contents => panic!("unexpected hover {contents:?}"),
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("status");
+        std::fs::write(&file, "before").unwrap();
+        let mut session = open_document(&format!("test create $(cat {})", file.display())).await;
+
+        assert!(hover(&mut session).await.contains("before"));
+        std::fs::write(&file, "after").unwrap();
+        assert!(hover(&mut session).await.contains("before"));
+
+        let params = DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: session.uri.clone(),
+            },
+            text: None,
+        };
+        session.client.did_save(params).unwrap();
+        assert!(hover(&mut session).await.contains("after"));
+    }
+
+    /// Text edits and file operations of a workspace edit. Both kinds of `document_changes` look
+    /// alike on the wire, so the client may decode either one.
+    fn split_document_changes(edit: WorkspaceEdit) -> (Vec<TextDocumentEdit>, Vec<ResourceOp>) {
+        match edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => (edits, Vec::new()),
+            Some(DocumentChanges::Operations(operations)) => {
+                let mut edits = Vec::new();
+                let mut resource_ops = Vec::new();
+                for operation in operations {
+                    match operation {
+                        DocumentChangeOperation::Edit(edit) => edits.push(edit),
+                        DocumentChangeOperation::Op(op) => resource_ops.push(op),
+                    }
+                }
+                (edits, resource_ops)
+            }
+            None => panic!("expected document changes, got {edit:?}"),
+        }
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn templates_are_found_in_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        let verbs = workspace.path().join(".lakonik/templates/verbs");
+        std::fs::create_dir_all(&verbs).unwrap();
+        std::fs::write(verbs.join("testworkspaceverb"), "workspace body").unwrap();
+
+        let (clean, pos) = find_hover_position("robot testwork***spaceverb foo");
+        let mut session = open_document_in(&clean, Some(workspace.path())).await;
+        let hover = session
+            .client
+            .hover(HoverParams {
+                text_document_position_params: position_params(&session, pos),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await
+            .unwrap();
+
+        let Some(Hover {
+            contents: HoverContents::Scalar(MarkedString::String(text)),
+            ..
+        }) = hover
+        else {
+            panic!("expected a hover, got {hover:?}");
+        };
+        assert!(text.contains("workspace body"), "{text}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn files_and_previews_resolve_against_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(workspace.path().join("testnotes.txt"), "from the workspace").unwrap();
+        let hover_at = |source: &'static str| {
+            let root = workspace.path().to_path_buf();
+            async move {
+                let (clean, pos) = find_hover_position(source);
+                let mut session = open_document_in(&clean, Some(&root)).await;
+                let params = position_params(&session, pos);
+                let hover = session
+                    .client
+                    .hover(HoverParams {
+                        text_document_position_params: params.clone(),
+                        work_done_progress_params: WorkDoneProgressParams::default(),
+                    })
+                    .await
+                    .unwrap();
+                let definition = session
+                    .client
+                    .definition(GotoDefinitionParams {
+                        text_document_position_params: params,
+                        work_done_progress_params: WorkDoneProgressParams::default(),
+                        partial_result_params: Default::default(),
+                    })
+                    .await
+                    .unwrap();
+                (format!("{hover:?}"), definition)
+            }
+        };
+
+        let (hover, definition) = hover_at("robot create @testno***tes.txt").await;
+        assert!(hover.contains("from the workspace"), "{hover}");
+        assert_eq!(
+            definition,
+            Some(GotoDefinitionResponse::Scalar(lsp_types::Location::new(
+                Url::from_file_path(workspace.path().join("testnotes.txt")).unwrap(),
+                lsp_types::Range::default(),
+            )))
+        );
+
+        let (hover, _) = hover_at("robot create $(cat testno***tes.txt)").await;
+        assert!(hover.contains("from the workspace"), "{hover}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn rename_updates_workspace_documents() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(workspace.path().join("a.lk"), "robot foo lorem").unwrap();
+        std::fs::write(workspace.path().join("b.lk"), "robot create lorem").unwrap();
+        std::fs::create_dir(workspace.path().join(".hidden")).unwrap();
+        std::fs::write(workspace.path().join(".hidden/c.lk"), "robot foo ipsum").unwrap();
+
+        let (clean, pos) = find_hover_position("test ~fo***o=(bar) baz");
+        let mut session = open_document_in(&clean, Some(workspace.path())).await;
+        let params = RenameParams {
+            text_document_position: position_params(&session, pos),
+            new_name: "qux".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let edit = session.client.rename(params).await.unwrap().unwrap();
+        let (edits, operations) = split_document_changes(edit);
+        assert!(operations.is_empty(), "{operations:?}");
+        let mut changes = edits
+            .into_iter()
+            .map(|edit| {
+                let file_name = edit
+                    .text_document
+                    .uri
+                    .path_segments()
+                    .unwrap()
+                    .next_back()
+                    .unwrap()
+                    .to_string();
+                let edits = edit
+                    .edits
+                    .into_iter()
+                    .map(|e| match e {
+                        OneOf::Left(e) => {
+                            (e.range.start.character, e.range.end.character, e.new_text)
+                        }
+                        OneOf::Right(e) => panic!("unexpected annotated edit {e:?}"),
+                    })
+                    .collect::<Vec<_>>();
+                (file_name, edits)
+            })
+            .collect::<Vec<_>>();
+        changes.sort();
+
+        assert_eq!(
+            changes,
+            vec![
+                ("a.lk".to_string(), vec![(6, 9, "qux".to_string())]),
+                ("testfile".to_string(), vec![(6, 9, "qux".to_string())]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn rename_follows_abbreviations_and_leaves_the_move_to_the_client() {
+        let config = crate::templates::TestUserDir::new();
+        crate::templates::create_user_template(
+            "verbs/testrenameme",
+            "+++\naliases = [\"testrn\"]\n+++\n{{ description }}",
+        )
+        .unwrap();
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(workspace.path().join("a.lk"), "robot testrenameme lorem").unwrap();
+        std::fs::write(workspace.path().join("b.lk"), "robot testrn lorem").unwrap();
+        std::fs::write(workspace.path().join("c.lk"), "robot create lorem").unwrap();
+
+        let (clean, pos) = find_hover_position("robot test***ren foo");
+        let mut session = open_document_in(&clean, Some(workspace.path())).await;
+        let params = RenameParams {
+            text_document_position: position_params(&session, pos),
+            new_name: "testrenamed".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let edit = session.client.rename(params).await.unwrap().unwrap();
+        let (edits, operations) = split_document_changes(edit);
+        let mut renamed = edits
+            .iter()
+            .map(|edit| edit.text_document.uri.path().rsplit('/').next().unwrap())
+            .collect::<Vec<_>>();
+        renamed.sort();
+        assert_eq!(renamed, vec!["a.lk", "b.lk", "testfile"]);
+
+        let old_path = config.path().join("verbs/testrenameme");
+        assert_eq!(
+            operations,
+            vec![ResourceOp::Rename(RenameFile {
+                old_uri: Url::from_file_path(&old_path).unwrap(),
+                new_uri: Url::from_file_path(config.path().join("verbs/testrenamed")).unwrap(),
+                options: Some(RenameFileOptions {
+                    overwrite: Some(false),
+                    ignore_if_exists: Some(false),
+                }),
+                annotation_id: None,
+            })]
+        );
+        assert!(old_path.exists());
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn rename_rejects_invalid_names() {
+        let (clean, pos) = find_hover_position("test ~fo***o=(bar) baz");
+        let mut session = open_document(&clean).await;
+        let params = RenameParams {
+            text_document_position: position_params(&session, pos),
+            new_name: "Not Valid".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let err = session.client.rename(params).await.unwrap_err();
+        assert!(err.to_string().contains("not a valid verb name"), "{err}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn workspace_symbols_find_verbs_and_sentences() {
+        let mut session = open_document("test create foobar").await;
+
+        let response = session
+            .client
+            .symbol(WorkspaceSymbolParams {
+                query: "crea".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        // Both variants have the same shape on the wire, so the client may decode either one
+        let symbols = match response {
+            Some(WorkspaceSymbolResponse::Flat(symbols)) => symbols
+                .into_iter()
+                .map(|s| (s.name, s.location.uri.to_string()))
+                .collect::<Vec<_>>(),
+            Some(WorkspaceSymbolResponse::Nested(symbols)) => symbols
+                .into_iter()
+                .map(|s| match s.location {
+                    OneOf::Left(location) => (s.name, location.uri.to_string()),
+                    OneOf::Right(location) => (s.name, location.uri.to_string()),
+                })
+                .collect::<Vec<_>>(),
+            None => panic!("expected symbols"),
+        };
+
+        assert!(symbols.contains(&(
+            "create".to_string(),
+            "lakonik-builtin:///verbs/create".to_string()
+        )));
+        assert!(symbols.contains(&("test create".to_string(), "file:///testfile".to_string())));
+        assert!(!symbols.iter().any(|(name, _)| name.contains("base")));
+    }
 }
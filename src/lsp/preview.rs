@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use lsp_types::Position;
+use tokio::io::AsyncReadExt;
+use tokio::time::timeout;
+
+use crate::ast::utils::RangeContainsPosition;
+use crate::config::project_root;
+use crate::hir::part::AnalyzedPart;
+use crate::hir::sentence::AnalyzedSentence;
+use crate::shell::{self, ShellError, ShellPolicy};
+
+/// Number of lines shown in a preview
+const PREVIEW_LINES: usize = 10;
+
+/// Upper bound on how much of a file is read to build its preview
+const PREVIEW_MAX_BYTES: u64 = 64 * 1024;
+
+/// Previews that take longer than this are abandoned
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Outputs of shell previews, keyed by the code that produced them
+pub type ShellPreviewCache = Arc<Mutex<HashMap<String, String>>>;
+
+/// Something under the cursor that is worth previewing, resolved lazily once hover is requested
+pub enum PreviewTarget {
+    File(String),
+    Shell(String),
+}
+
+pub fn find_preview_target(analyzed: &AnalyzedSentence, pos: &Position) -> Option<PreviewTarget> {
+    analyzed.parts.iter().find_map(|part| match part {
+        AnalyzedPart::FilePath(p) if p.node.range.contains_position(pos) => {
+            Some(PreviewTarget::File(p.node.path.clone()))
+        }
+        // Only plain shell code can be checked for side effects
+        AnalyzedPart::InlineShell(p)
+            if p.node.interpreter.is_none() && p.node.range.contains_position(pos) =>
+        {
+            Some(PreviewTarget::Shell(p.node.code.clone()))
+        }
+        _ => None,
+    })
+}
+
+/// Resolves a path of a sentence the way `eval` would when run from the workspace, rather than
+/// from wherever the editor started the server
+pub fn workspace_path(path: &str) -> PathBuf {
+    match project_root() {
+        Some(root) => root.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Previews run in the workspace too, so that they show what `eval` would see there
+fn preview_policy() -> ShellPolicy {
+    ShellPolicy {
+        working_dir: project_root(),
+        ..ShellPolicy::read_only_preview()
+    }
+}
+
+/// A command is safe to dry-run if every one of its commands is known to only read state
+pub fn is_safe_for_preview(code: &str) -> bool {
+    ShellPolicy::read_only_preview().check(code).is_ok()
+}
+
+fn first_lines(text: &str) -> String {
+    let mut lines = text.lines();
+    let mut preview = lines
+        .by_ref()
+        .take(PREVIEW_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if lines.next().is_some() {
+        preview.push_str("\n…");
+    }
+
+    preview
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_age(modified: SystemTime) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default()
+        .as_secs();
+
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} minutes ago", seconds / 60),
+        3600..86400 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// The text of a file's first bytes, or `None` for binary files. The limit can cut the last
+/// character in half, which is dropped rather than taken for binary data.
+fn text_prefix(bytes: &[u8]) -> Option<&str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    }
+}
+
+async fn file_preview(path: &str) -> String {
+    let file_path = workspace_path(path);
+    let metadata = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return format!("_`{path}` does not exist_"),
+    };
+
+    let mut header = format!("`{path}` · {}", format_size(metadata.len()));
+    if let Ok(modified) = metadata.modified() {
+        header.push_str(&format!(" · modified {}", format_age(modified)));
+    }
+
+    if metadata.is_dir() {
+        return header;
+    }
+
+    let mut bytes = Vec::new();
+    let read = match tokio::fs::File::open(&file_path).await {
+        Ok(file) => file.take(PREVIEW_MAX_BYTES).read_to_end(&mut bytes).await,
+        Err(err) => Err(err),
+    };
+
+    match read.map(|_| text_prefix(&bytes)) {
+        Ok(Some(text)) => format!("{header}\n\n```\n{}\n```", first_lines(text)),
+        Ok(None) => format!("{header}\n\n_Binary file_"),
+        Err(err) => format!("{header}\n\n_Could not read file: {err}_"),
+    }
+}
+
+/// Dry-runs a command that is safe to preview, reusing earlier results for the same code
+pub async fn shell_output(
+    code: &str,
+    cache: &ShellPreviewCache,
+) -> Option<Result<String, ShellError>> {
+    if !is_safe_for_preview(code) {
+        return None;
+    }
+
+    if let Some(cached) = cache.lock().unwrap().get(code) {
+        return Some(Ok(cached.clone()));
+    }
+
+    let output = shell::run(None, code, &preview_policy())
+        .await
+        .map(|output| output.stdout + &output.stderr);
+    if let Ok(output) = &output {
+        cache
+            .lock()
+            .unwrap()
+            .insert(code.to_string(), output.clone());
+    }
+
+    Some(output)
+}
+
+async fn shell_preview(code: &str, cache: &ShellPreviewCache) -> String {
+    match shell_output(code, cache).await {
+        None => "_No preview: command is not marked safe_".to_string(),
+        Some(Ok(output)) => format!("```\n{}\n```", first_lines(output.trim_end())),
+        Some(Err(err)) => format!("_Could not run command: {err}_"),
+    }
+}
+
+pub async fn render_preview(target: PreviewTarget, cache: ShellPreviewCache) -> String {
+    let preview = async {
+        match &target {
+            PreviewTarget::File(path) => file_preview(path).await,
+            PreviewTarget::Shell(code) => shell_preview(code, &cache).await,
+        }
+    };
+
+    timeout(PREVIEW_TIMEOUT, preview)
+        .await
+        .unwrap_or_else(|_| "_Preview timed out_".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serial_test::serial;
+
+    #[rstest]
+    #[case("ls", true)]
+    #[case("cat README.md", true)]
+    #[case("git diff | grep foo | wc -l", true)]
+    #[case("git diff --output=patch", false)]
+    #[case("git log --output patch", false)]
+    #[case("tree -o out", false)]
+    #[case("date -s 2000-01-01", false)]
+    #[case("git push", false)]
+    #[case("git", false)]
+    #[case("rm -rf /", false)]
+    #[case("ls; rm -rf /", false)]
+    #[case("echo $(rm foo)", false)]
+    #[case("cat foo > bar", false)]
+    #[case("ls |", false)]
+    fn safe_for_preview(#[case] code: &str, #[case] expected: bool) {
+        assert_eq!(is_safe_for_preview(code), expected);
+    }
+
+    #[rstest]
+    #[case(512, "512 B")]
+    #[case(2048, "2.0 KiB")]
+    #[case(5 * 1024 * 1024, "5.0 MiB")]
+    fn size_formatting(#[case] bytes: u64, #[case] expected: &str) {
+        assert_eq!(format_size(bytes), expected);
+    }
+
+    #[test]
+    fn long_outputs_are_truncated() {
+        let text = (1..=20)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let preview = first_lines(&text);
+
+        assert!(preview.starts_with("1\n2\n"));
+        assert!(preview.ends_with("10\n…"));
+    }
+
+    #[tokio::test]
+    async fn characters_cut_by_the_limit_are_not_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let mut text = "a".repeat(PREVIEW_MAX_BYTES as usize - 1);
+        text.push('é');
+        std::fs::write(&path, text).unwrap();
+        let binary = dir.path().join("binary");
+        std::fs::write(&binary, [b'a', 0xff, b'b']).unwrap();
+
+        let preview = file_preview(&path.to_string_lossy()).await;
+        assert!(preview.contains("\n```\naaaa"), "{preview}");
+        assert!(!preview.contains('é'));
+        let preview = file_preview(&binary.to_string_lossy()).await;
+        assert!(preview.ends_with("_Binary file_"), "{preview}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn shell_previews_are_cached() {
+        let cache = ShellPreviewCache::default();
+        cache
+            .lock()
+            .unwrap()
+            .insert("echo hello".to_string(), "cached".to_string());
+
+        assert_eq!(
+            shell_preview("echo hello", &cache).await,
+            "```\ncached\n```"
+        );
+        assert!(shell_preview("echo world", &cache).await.contains("world"));
+        assert!(cache.lock().unwrap().contains_key("echo world"));
+    }
+}
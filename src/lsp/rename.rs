@@ -0,0 +1,125 @@
+use async_lsp::{ErrorCode, ResponseError};
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
+    Position, PrepareRenameResponse, RenameFile, RenameFileOptions, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::ast::utils::RangeContainsPosition;
+use crate::ast::{Sentence, Verb, is_valid_name};
+use crate::hir::sentence::AnalyzedSentence;
+use crate::hir::verb::resolve_verb;
+use crate::templates::{TemplateSource, template_move};
+
+use super::workspace::WorkspaceDocument;
+
+fn request_failed(message: impl std::fmt::Display) -> ResponseError {
+    ResponseError::new(ErrorCode::REQUEST_FAILED, message)
+}
+
+/// Built-in verbs are read-only, everything else can be renamed
+fn check_renameable(analyzed: &AnalyzedSentence) -> Result<(), ResponseError> {
+    let is_built_in = analyzed
+        .verb
+        .template
+        .as_ref()
+        .is_some_and(|t| t.source == TemplateSource::BuiltIn);
+
+    if is_built_in && !analyzed.verb.is_assignment() {
+        return Err(request_failed(format!(
+            "`{}` is a built-in verb and cannot be renamed",
+            analyzed.verb.node.name()
+        )));
+    }
+
+    Ok(())
+}
+
+/// The full name of the verb a sentence uses, so that `cr`, `create` and an alias of `create`
+/// are all found as uses of `create`
+fn resolved_verb_name(sentence: &Sentence) -> String {
+    match &sentence.verb {
+        Verb::Simple(verb) => resolve_verb(&verb.name)
+            .0
+            .and_then(|t| t.verb_name().map(str::to_string))
+            .unwrap_or_else(|| verb.name.clone()),
+        Verb::Assignment(verb) => verb.name.clone(),
+    }
+}
+
+pub fn prepare_rename(
+    analyzed: &AnalyzedSentence,
+    pos: &Position,
+) -> Result<Option<PrepareRenameResponse>, ResponseError> {
+    let range = analyzed.verb.node.name_range();
+    if !range.contains_position(pos) {
+        return Ok(None);
+    }
+    check_renameable(analyzed)?;
+
+    Ok(Some(PrepareRenameResponse::Range(range)))
+}
+
+/// Renames the verb under the cursor: every sentence in the workspace that uses it is updated,
+/// and its template is moved. The move is part of the returned edit, so nothing changes on disk
+/// unless the editor applies it.
+pub fn rename(
+    analyzed: &AnalyzedSentence,
+    pos: &Position,
+    new_name: &str,
+    documents: &[WorkspaceDocument],
+) -> Result<Option<WorkspaceEdit>, ResponseError> {
+    if !analyzed.verb.node.name_range().contains_position(pos) {
+        return Ok(None);
+    }
+    check_renameable(analyzed)?;
+
+    if !is_valid_name(new_name) {
+        return Err(request_failed(format!(
+            "`{new_name}` is not a valid verb name"
+        )));
+    }
+
+    let old_name = &analyzed.verb.name;
+    let mut operations = documents
+        .iter()
+        .filter(|doc| {
+            !doc.sentence.verb.is_inferred() && resolved_verb_name(&doc.sentence) == *old_name
+        })
+        .map(|doc| {
+            let edit = TextEdit::new(doc.sentence.verb.name_range(), new_name.to_string());
+            DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: doc.uri.clone(),
+                    version: None,
+                },
+                edits: vec![OneOf::Left(edit)],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(template) = &analyzed.verb.template
+        && let Some((old_path, new_path)) =
+            template_move(template, &format!("verbs/{new_name}")).map_err(request_failed)?
+    {
+        let uri = |path| {
+            Url::from_file_path(path).map_err(|_| request_failed("template path is not absolute"))
+        };
+        operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(
+            RenameFile {
+                old_uri: uri(old_path)?,
+                new_uri: uri(new_path)?,
+                options: Some(RenameFileOptions {
+                    overwrite: Some(false),
+                    ignore_if_exists: Some(false),
+                }),
+                annotation_id: None,
+            },
+        )));
+    }
+
+    Ok(Some(WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(operations)),
+        ..WorkspaceEdit::default()
+    }))
+}
@@ -0,0 +1,253 @@
+use lsp_types::{
+    Position, Range, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend,
+};
+
+use crate::ast::Verb;
+use crate::hir::part::AnalyzedPart;
+use crate::hir::sentence::AnalyzedSentence;
+use crate::hir::utils::Analyzed;
+use crate::templates::TemplateSource;
+
+// Indices into `TOKEN_TYPES`
+const VOCATIVE: u32 = 0;
+const VERB: u32 = 1;
+const VERB_ASSIGNMENT: u32 = 2;
+const FREEFORM: u32 = 3;
+const FILE_PATH: u32 = 4;
+const INLINE_SHELL: u32 = 5;
+
+const TOKEN_TYPES: [SemanticTokenType; 6] = [
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::STRING,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::MACRO,
+];
+
+// Bit flags, in the same order as `token_modifiers()`
+const DEFINITION: u32 = 1 << 0;
+const DEFAULT_LIBRARY: u32 = 1 << 1;
+const MISSING: u32 = 1 << 2;
+
+fn token_modifiers() -> Vec<SemanticTokenModifier> {
+    vec![
+        SemanticTokenModifier::DEFINITION,
+        SemanticTokenModifier::DEFAULT_LIBRARY,
+        SemanticTokenModifier::new("missing"),
+    ]
+}
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: token_modifiers(),
+    }
+}
+
+/// A token with an absolute range, before delta encoding
+#[derive(Debug, PartialEq)]
+struct RawToken {
+    range: Range,
+    token_type: u32,
+    modifiers: u32,
+}
+
+fn collect_tokens(analyzed: &AnalyzedSentence) -> Vec<RawToken> {
+    let mut tokens = vec![RawToken {
+        range: *analyzed.vocative.get_range(),
+        token_type: VOCATIVE,
+        modifiers: 0,
+    }];
+
+    let verb_modifiers = match analyzed.verb.template.as_ref().map(|t| t.source) {
+        Some(TemplateSource::BuiltIn) => DEFAULT_LIBRARY,
+        Some(TemplateSource::User | TemplateSource::Project) => 0,
+        None => MISSING,
+    };
+    tokens.push(match &analyzed.verb.node {
+        Verb::Simple(node) => RawToken {
+            range: node.range,
+            token_type: VERB,
+            modifiers: verb_modifiers,
+        },
+        Verb::Assignment(node) => RawToken {
+            range: node.range,
+            token_type: VERB_ASSIGNMENT,
+            modifiers: DEFINITION,
+        },
+    });
+
+    tokens.extend(analyzed.parts.iter().map(|part| match part {
+        AnalyzedPart::Freeform(p) => RawToken {
+            range: p.node.range,
+            token_type: FREEFORM,
+            modifiers: 0,
+        },
+        AnalyzedPart::FilePath(p) => RawToken {
+            range: p.node.range,
+            token_type: FILE_PATH,
+            modifiers: if p.exists { 0 } else { MISSING },
+        },
+        AnalyzedPart::InlineShell(p) => RawToken {
+            range: p.node.range,
+            token_type: INLINE_SHELL,
+            modifiers: 0,
+        },
+        AnalyzedPart::Stdin(p) => RawToken {
+            range: p.node.range,
+            token_type: FILE_PATH,
+            modifiers: 0,
+        },
+    }));
+    // Inferred vocatives and verbs are not in the text
+    tokens.retain(|t| t.range.start != t.range.end);
+
+    tokens
+}
+
+fn overlaps(a: &Range, b: &Range) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Splits a multi-line token into one single-line range per line, since not every client
+/// supports tokens that span several lines
+fn single_line_ranges(range: &Range, text: &str) -> Vec<Range> {
+    if range.start.line == range.end.line {
+        return vec![*range];
+    }
+
+    (range.start.line..=range.end.line)
+        .map(|line| {
+            let line_length = text.lines().nth(line as usize).map_or(0, str::len) as u32;
+            let start = if line == range.start.line {
+                range.start.character
+            } else {
+                0
+            };
+            let end = if line == range.end.line {
+                range.end.character
+            } else {
+                line_length
+            };
+
+            Range::new(Position::new(line, start), Position::new(line, end))
+        })
+        .filter(|r| r.start.character < r.end.character)
+        .collect()
+}
+
+/// Encodes the tokens of a document, optionally restricted to those touching `within`
+pub fn semantic_tokens(
+    analyzed: &AnalyzedSentence,
+    text: &str,
+    within: Option<&Range>,
+) -> Vec<SemanticToken> {
+    let mut previous = Position::new(0, 0);
+
+    collect_tokens(analyzed)
+        .into_iter()
+        .filter(|token| within.is_none_or(|r| overlaps(&token.range, r)))
+        .flat_map(|token| {
+            single_line_ranges(&token.range, text)
+                .into_iter()
+                .map(move |range| (range, token.token_type, token.modifiers))
+        })
+        .map(|(range, token_type, modifiers)| {
+            let delta_line = range.start.line - previous.line;
+            let delta_start = if delta_line == 0 {
+                range.start.character - previous.character
+            } else {
+                range.start.character
+            };
+            previous = range.start;
+
+            SemanticToken {
+                delta_line,
+                delta_start,
+                length: range.end.character - range.start.character,
+                token_type,
+                token_modifiers_bitset: modifiers,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Span, parse_statement};
+    use crate::hir::utils::{AnalysisContext, Analyzable};
+    use rstest::rstest;
+    use serial_test::serial;
+
+    fn tokens_for(text: &str, within: Option<&Range>) -> Vec<(u32, u32, u32, u32, u32)> {
+        let (_, ast) = parse_statement(Span::new(text)).unwrap();
+        let analyzed = ast.analyze(&mut AnalysisContext::default());
+
+        semantic_tokens(&analyzed, text, within)
+            .into_iter()
+            .map(|t| {
+                (
+                    t.delta_line,
+                    t.delta_start,
+                    t.length,
+                    t.token_type,
+                    t.token_modifiers_bitset,
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case(
+        "qwen3 create foo @Cargo.toml @nope.txt $(ls)",
+        vec![
+            (0, 0, 5, VOCATIVE, 0),
+            (0, 6, 6, VERB, DEFAULT_LIBRARY),
+            (0, 7, 3, FREEFORM, 0),
+            (0, 5, 10, FILE_PATH, 0),
+            (0, 12, 8, FILE_PATH, MISSING),
+            (0, 11, 2, INLINE_SHELL, 0),
+        ]
+    )]
+    #[case(
+        "robot ~foo=(bar baz) lorem",
+        vec![
+            (0, 0, 5, VOCATIVE, 0),
+            (0, 6, 14, VERB_ASSIGNMENT, DEFINITION),
+            (0, 15, 5, FREEFORM, 0),
+        ]
+    )]
+    #[case(
+        "robot nosuchverb\n  lorem",
+        vec![
+            (0, 0, 5, VOCATIVE, 0),
+            (0, 6, 10, VERB, MISSING),
+            (1, 2, 5, FREEFORM, 0),
+        ]
+    )]
+    #[case(
+        "robot create $(echo a\necho bc)",
+        vec![
+            (0, 0, 5, VOCATIVE, 0),
+            (0, 6, 6, VERB, DEFAULT_LIBRARY),
+            (0, 9, 6, INLINE_SHELL, 0),
+            (1, 0, 7, INLINE_SHELL, 0),
+        ]
+    )]
+    fn full_document_tokens(#[case] text: &str, #[case] expected: Vec<(u32, u32, u32, u32, u32)>) {
+        assert_eq!(tokens_for(text, None), expected);
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn range_request_only_returns_overlapping_tokens() {
+        let within = Range::new(Position::new(0, 7), Position::new(0, 15));
+
+        assert_eq!(
+            tokens_for("qwen3 create foo bar", Some(&within)),
+            vec![(0, 6, 6, VERB, DEFAULT_LIBRARY), (0, 7, 3, FREEFORM, 0)]
+        );
+    }
+}
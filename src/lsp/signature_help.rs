@@ -0,0 +1,87 @@
+use lsp_types::{
+    Documentation, MarkupContent, MarkupKind, ParameterInformation, ParameterLabel, Position,
+    SignatureHelp, SignatureInformation,
+};
+
+use crate::hir::sentence::AnalyzedSentence;
+use crate::templates::build_environment;
+
+/// Describes the verb of the sentence while the cursor is on the verb or on one of its parts
+pub fn signature_help(analyzed: &AnalyzedSentence, pos: &Position) -> Option<SignatureHelp> {
+    if *pos < analyzed.verb.node.name_range().start {
+        return None;
+    }
+
+    let template = analyzed.verb.template.as_ref()?;
+    let variables = build_environment()
+        .map(|environment| template.variables(&environment))
+        .unwrap_or_default();
+    let name = analyzed.verb.node.name();
+
+    let label = std::iter::once(name.to_string())
+        .chain(variables.iter().map(|v| format!("<{v}>")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let parameters = variables
+        .iter()
+        .map(|v| ParameterInformation {
+            label: ParameterLabel::Simple(format!("<{v}>")),
+            documentation: None,
+        })
+        .collect();
+    let description = template
+        .description()
+        .unwrap_or_else(|| format!("_Verb_ **{name}**"));
+
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation: Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: description,
+            })),
+            parameters: Some(parameters),
+            active_parameter: None,
+        }],
+        active_signature: Some(0),
+        active_parameter: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Span, parse_statement};
+    use crate::hir::utils::{AnalysisContext, Analyzable};
+    use serial_test::serial;
+
+    fn help_for(text: &str, character: u32) -> Option<SignatureHelp> {
+        let (_, ast) = parse_statement(Span::new(text)).unwrap();
+        let analyzed = ast.analyze(&mut AnalysisContext::default());
+
+        signature_help(&analyzed, &Position::new(0, character))
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn describes_built_in_verb() {
+        let help = help_for("robot create foo", 14).expect("expected signature help");
+        let signature = &help.signatures[0];
+
+        assert_eq!(signature.label, "create <description>");
+        assert_eq!(
+            signature.documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "Ask for something to be created".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn no_help_on_vocative_or_unknown_verb() {
+        assert!(help_for("robot create foo", 2).is_none());
+        assert!(help_for("robot nosuchverb foo", 8).is_none());
+    }
+}
@@ -0,0 +1,48 @@
+use lsp_types::{Location, OneOf, SymbolKind, WorkspaceSymbol};
+
+use crate::templates::get_all_templates;
+
+use super::definition::template_uri;
+use super::workspace::WorkspaceDocument;
+
+fn matches(name: &str, query: &str) -> bool {
+    name.to_lowercase().contains(&query.to_lowercase())
+}
+
+/// Finds verbs whose name matches `query`, along with every sentence that uses them
+pub fn workspace_symbols(query: &str, documents: &[WorkspaceDocument]) -> Vec<WorkspaceSymbol> {
+    let verbs = get_all_templates()
+        .filter_map(|t| Some((t.verb_name()?.to_string(), t)))
+        .filter(|(name, _)| matches(name, query))
+        .filter_map(|(name, template)| {
+            Some(WorkspaceSymbol {
+                name,
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                container_name: Some(format!("{:?}", template.source)),
+                location: OneOf::Left(Location::new(template_uri(&template)?, Default::default())),
+                data: None,
+            })
+        });
+
+    let sentences = documents
+        .iter()
+        .filter(|doc| matches(doc.sentence.verb.name(), query))
+        .map(|doc| WorkspaceSymbol {
+            name: format!(
+                "{} {}",
+                doc.sentence.vocative.name,
+                doc.sentence.verb.name()
+            ),
+            kind: SymbolKind::EVENT,
+            tags: None,
+            container_name: Some(doc.sentence.verb.name().to_string()),
+            location: OneOf::Left(Location::new(
+                doc.uri.clone(),
+                doc.sentence.verb.name_range(),
+            )),
+            data: None,
+        });
+
+    verbs.chain(sentences).collect()
+}
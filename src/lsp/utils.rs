@@ -1,22 +1,30 @@
-use crate::ast::{Sentence, Span, parse_statement};
+use crate::ast::{Sentence, Span, parse_statement_with};
+use crate::engine::Defaults;
 use crate::hir::utils::{AnalysisContext, Analyzable};
 use lsp_types::Url;
 use std::collections::HashMap;
 
 use super::DocumentState;
 
-pub fn parse(input: &str) -> Option<Sentence> {
+pub fn parse(input: &str, defaults: &Defaults) -> Option<Sentence> {
     let span = Span::new(input);
-    parse_statement(span).ok().map(|(_, sentence)| sentence)
+    parse_statement_with(span, defaults)
+        .ok()
+        .map(|(_, sentence)| sentence)
 }
 
-pub fn update_document(docs: &mut HashMap<Url, DocumentState>, uri: Url, text: String) {
-    if let Some(ast) = parse(&text) {
-        let analyzed = ast.analyze(&mut AnalysisContext {});
-        eprintln!("Parsed document: {analyzed:?}");
-        docs.insert(uri, DocumentState { analyzed });
-    } else {
-        docs.remove(&uri);
-        tracing::warn!("Could not parse document: {}", uri);
+pub fn update_document(
+    docs: &mut HashMap<Url, DocumentState>,
+    uri: Url,
+    text: String,
+    defaults: &Defaults,
+) {
+    let analyzed = parse(&text, defaults).map(|ast| ast.analyze(&mut AnalysisContext::default()));
+    match &analyzed {
+        Some(analyzed) => eprintln!("Parsed document: {analyzed:?}"),
+        None => tracing::warn!("Could not parse document: {}", uri),
     }
+
+    // The text is kept either way, completion works on sentences that don't parse yet
+    docs.insert(uri, DocumentState { analyzed, text });
 }
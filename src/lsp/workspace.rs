@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use lsp_types::{InitializeParams, Url};
+use walkdir::WalkDir;
+
+use crate::ast::Sentence;
+use crate::engine::Defaults;
+
+use super::DocumentState;
+use super::utils::parse;
+
+/// File extension of Lakonik documents
+const EXTENSION: &str = "lk";
+
+/// A parsed Lakonik document, either open in the editor or read from disk
+pub struct WorkspaceDocument {
+    pub uri: Url,
+    pub sentence: Sentence,
+}
+
+pub fn workspace_roots(params: &InitializeParams) -> Vec<PathBuf> {
+    let folders = params.workspace_folders.iter().flatten().map(|f| &f.uri);
+    #[allow(deprecated)]
+    let root_uri = params.root_uri.iter();
+
+    folders
+        .chain(root_uri)
+        .filter_map(|uri| uri.to_file_path().ok())
+        .collect()
+}
+
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Collects every document in the workspace; open documents win over their contents on disk
+pub fn workspace_documents(
+    open: &HashMap<Url, DocumentState>,
+    roots: &[PathBuf],
+    defaults: &Defaults,
+) -> Vec<WorkspaceDocument> {
+    let mut documents: Vec<WorkspaceDocument> = open
+        .iter()
+        .filter_map(|(uri, doc)| {
+            Some(WorkspaceDocument {
+                uri: uri.clone(),
+                sentence: doc.analyzed.as_ref()?.node.clone(),
+            })
+        })
+        .collect();
+
+    let on_disk = roots
+        .iter()
+        .flat_map(|root| {
+            WalkDir::new(root)
+                .into_iter()
+                .filter_entry(|e| !is_hidden(e))
+        })
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == EXTENSION))
+        .filter_map(|entry| {
+            let uri = Url::from_file_path(entry.path()).ok()?;
+            if open.contains_key(&uri) {
+                return None;
+            }
+            let sentence = parse(&fs::read_to_string(entry.path()).ok()?, defaults)?;
+
+            Some(WorkspaceDocument { uri, sentence })
+        })
+        .collect::<Vec<_>>();
+
+    documents.extend(on_disk);
+    documents.sort_by(|a, b| a.uri.cmp(&b.uri));
+    documents.dedup_by(|a, b| a.uri == b.uri);
+
+    documents
+}
@@ -1,13 +1,29 @@
 mod ast;
+mod config;
+mod context;
 mod engine;
+mod error;
 mod hir;
 mod lsp;
+mod shell;
 mod templates;
 
-use anyhow::{Ok, Result};
-use clap::{Parser, Subcommand};
-use engine::run_prompt_builder;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use config::{Config, ConfigLayer, ShellSettings};
+use engine::{PromptInputs, run_prompt_builder, stdin_span};
+use error::LakonikError;
 use lsp::run_lsp_server;
+use shell::{OnFailure, ShellError, ShellOutput, ShellPolicy};
+use templates::TemplateSource;
+use templates::check::{self, Severity};
+use templates::fixtures::{self, CaseOutcome};
+use templates::functions::TemplatePolicy;
+use templates::manage;
 
 #[derive(Parser, Debug)]
 #[command(name = "lakonik", version, about, trailing_var_arg = true)]
@@ -25,43 +41,643 @@ enum Commands {
         #[arg(short, long)]
         verbose: bool,
 
+        /// Maximum combined size of attached files and piped input [default: `attachments.max_bytes`]
+        #[arg(long, value_name = "BYTES")]
+        max_attachment_bytes: Option<u64>,
+
+        /// Let templates read this environment variable with `env(name)`
+        #[arg(long = "template-env", value_name = "NAME")]
+        template_env: Vec<String>,
+
+        /// Don't save the templates that `~verb=(...)` and `~verb!=(...)` define
+        #[arg(long)]
+        no_persist: bool,
+
+        #[command(flatten)]
+        shell: ShellPolicyArgs,
+
         /// Everything after flags is the prompt definition
         #[arg(num_args = 0..)]
         input: Vec<String>,
     },
     /// Language server protocol placeholder: prints "hello world"
     Lsp {},
+    /// Inspect the templates behind verbs, vocatives and parts
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+    /// Read and change the settings of `config.toml`
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the value of a setting such as `shell.timeout`
+    Get {
+        /// Dotted key, e.g. `default_vocative` or `providers.openai.api_key`
+        key: String,
+    },
+    /// Write a setting to the user or project `config.toml`
+    Set {
+        /// Dotted key, e.g. `shell.timeout`
+        key: String,
+
+        /// A TOML value such as `10` or `["git", "ls"]`; anything else is taken as a string
+        value: String,
+
+        /// Write to `.lakonik/config.toml` of the project instead of the user `config.toml`
+        #[arg(long)]
+        project: bool,
+    },
+    /// List every setting with its value and the layer it comes from
+    List {},
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplatesCommand {
+    /// Show which layer a template is loaded from, and which layers it overrides
+    Which {
+        /// A verb or alias such as `create`, or a path such as `parts/shell`
+        name: String,
+    },
+    /// List the templates in use with their type, layer and description
+    List {},
+    /// Check every template for errors and likely mistakes, printing `file:line` diagnostics
+    Check {},
+    /// Render the sentences of the `.test.toml` fixtures next to templates, with stubbed shell
+    /// output, and compare them with the expected prompts
+    Test {
+        /// Rewrite the expected prompts that are missing or differ
+        #[arg(long)]
+        update: bool,
+    },
+    /// Print the contents of a template
+    Show {
+        /// A verb or alias such as `create`, or a path such as `parts/shell`
+        name: String,
+    },
+    /// Create a template that extends another one
+    New {
+        /// A verb name such as `review`, or a path such as `parts/review`
+        name: String,
+
+        /// Template to extend
+        #[arg(long, default_value = "verbs/base/base")]
+        from: String,
+
+        #[command(flatten)]
+        layer: LayerArgs,
+    },
+    /// Open a template in `$VISUAL` or `$EDITOR`, copying it into the layer first if needed
+    Edit {
+        /// A verb or alias such as `create`, or a path such as `parts/shell`
+        name: String,
+
+        #[command(flatten)]
+        layer: LayerArgs,
+    },
+    /// Delete a template from a layer
+    Delete {
+        /// A verb or alias such as `create`, or a path such as `parts/shell`
+        name: String,
+
+        #[command(flatten)]
+        layer: LayerArgs,
+    },
+    /// Export the templates of a layer to a directory or a `.tar.gz` file
+    Export {
+        /// Directory or `.tar.gz` file to write to
+        dest: PathBuf,
+
+        /// Only export these templates
+        names: Vec<String>,
+
+        #[command(flatten)]
+        layer: LayerArgs,
+    },
+    /// Import templates from a directory or a `.tar.gz` file into a layer
+    Import {
+        /// Directory or `.tar.gz` file to read from
+        src: PathBuf,
+
+        /// Overwrite templates that already exist in the layer
+        #[arg(long)]
+        force: bool,
+
+        #[command(flatten)]
+        layer: LayerArgs,
+    },
+}
+
+/// The layer that template management commands write to
+#[derive(Args, Debug)]
+struct LayerArgs {
+    /// Use the project templates in `.lakonik/templates` instead of the user templates
+    #[arg(long)]
+    project: bool,
+}
+
+impl LayerArgs {
+    fn source(&self) -> TemplateSource {
+        if self.project {
+            TemplateSource::Project
+        } else {
+            TemplateSource::User
+        }
+    }
+}
+
+/// Limits on the inline shell code (`$(...)`) of a prompt, on top of the `[shell]` settings
+#[derive(Args, Debug)]
+struct ShellPolicyArgs {
+    /// Only run these commands; an entry like `git diff` also restricts the subcommand
+    #[arg(long = "shell-allow", value_name = "COMMAND")]
+    allow: Vec<String>,
+
+    /// Never run these commands. Best-effort only, use the sandbox or --shell-allow to keep
+    /// untrusted code in check
+    #[arg(long = "shell-deny", value_name = "COMMAND")]
+    deny: Vec<String>,
+
+    /// Seconds a command may run before it is stopped [default: 30]
+    #[arg(long = "shell-timeout", value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// How many commands may run at the same time [default: 4]
+    #[arg(long = "shell-jobs", value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Maximum number of bytes a command may output [default: 1048576]
+    #[arg(long = "shell-max-output", value_name = "BYTES")]
+    max_output: Option<usize>,
+
+    /// Directory to run commands in
+    #[arg(long = "shell-cwd", value_name = "DIR")]
+    working_dir: Option<PathBuf>,
+
+    /// Only pass HOME, LANG, PATH, TERM and USER to commands
+    #[arg(long = "shell-minimal-env")]
+    minimal_env: bool,
+
+    /// Run commands in a bubblewrap sandbox
+    #[arg(long = "shell-sandbox")]
+    sandbox: bool,
+
+    /// Make the file system read-only inside the sandbox
+    #[arg(long = "shell-read-only")]
+    read_only: bool,
+
+    /// Disable network access inside the sandbox
+    #[arg(long = "shell-no-network")]
+    no_network: bool,
+
+    /// Ask for confirmation before running each command
+    #[arg(long = "shell-confirm")]
+    confirm: bool,
+
+    /// What to do when a command exits with a non-zero status [default: warn]
+    #[arg(long = "shell-on-failure", value_enum)]
+    on_failure: Option<OnFailure>,
+
+    /// Run `$TAG(...)` with this command line, e.g. `py=python3 -I -c`; the code is appended
+    #[arg(long = "shell-interpreter", value_name = "TAG=COMMAND", value_parser = parse_interpreter)]
+    interpreters: Vec<(String, Vec<String>)>,
+}
+
+fn parse_interpreter(value: &str) -> std::result::Result<(String, Vec<String>), String> {
+    let (tag, command) = value
+        .split_once('=')
+        .ok_or_else(|| "expected `TAG=COMMAND`".to_string())?;
+    let command = command
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    if command.is_empty() {
+        return Err(format!("no command given for `{tag}`"));
+    }
+
+    Ok((tag.to_string(), command))
+}
+
+impl ShellPolicyArgs {
+    /// Flags override the settings they name; lists of denied commands and interpreters add to
+    /// the settings. Flags that only shape the sandbox are an error when there is none.
+    fn apply(&self, settings: &mut ShellSettings) -> Result<(), LakonikError> {
+        if !self.allow.is_empty() {
+            settings.allow = Some(self.allow.clone());
+        }
+        settings.deny.extend(self.deny.iter().cloned());
+        if let Some(timeout) = self.timeout {
+            settings.timeout = timeout;
+        }
+        if let Some(jobs) = self.jobs {
+            settings.jobs = jobs;
+        }
+        if let Some(max_output) = self.max_output {
+            settings.max_output = max_output;
+        }
+        if let Some(working_dir) = &self.working_dir {
+            settings.working_dir = Some(working_dir.clone());
+        }
+        settings.minimal_env |= self.minimal_env;
+        settings.sandbox |= self.sandbox;
+        settings.read_only |= self.read_only;
+        settings.network &= !self.no_network;
+        settings.confirm |= self.confirm;
+        if let Some(on_failure) = self.on_failure {
+            settings.on_failure = on_failure;
+        }
+        settings
+            .interpreters
+            .extend(self.interpreters.iter().cloned());
+
+        if !settings.sandbox && (self.read_only || self.no_network) {
+            return Err(LakonikError::Config {
+                message: "--shell-read-only and --shell-no-network only apply inside the \
+                          sandbox, pass --shell-sandbox or set `shell.sandbox`"
+                    .to_string(),
+            });
+        }
+
+        Ok(())
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
-    match &cli.command {
-        Commands::Eval { verbose, input } => {
-            cmd_eval(*verbose, input).await?;
+    let exit_code = match &cli.command {
+        Commands::Eval {
+            verbose,
+            max_attachment_bytes,
+            template_env,
+            no_persist,
+            shell,
+            input,
+        } => {
+            let mut settings = match Config::load() {
+                Ok(config) => config.settings,
+                Err(err) => return Ok(report(Err(err))),
+            };
+            if let Err(err) = shell.apply(&mut settings.shell) {
+                return Ok(report(Err(err)));
+            }
+            let inputs = PromptInputs {
+                stdin: None,
+                max_attachment_bytes: max_attachment_bytes
+                    .unwrap_or(settings.attachments.max_bytes),
+                template_policy: TemplatePolicy {
+                    env: template_env.clone(),
+                    ..TemplatePolicy::default()
+                },
+                defaults: (&settings).into(),
+                fixture: None,
+            };
+
+            cmd_eval(
+                *verbose,
+                inputs,
+                *no_persist,
+                &(&settings.shell).into(),
+                input,
+            )
+            .await
         }
         Commands::Lsp {} => {
             cmd_lsp().await;
+            ExitCode::SUCCESS
         }
+        Commands::Templates { command } => cmd_templates(command).await,
+        Commands::Config { command } => report(cmd_config(command)),
+    };
+
+    Ok(exit_code)
+}
+
+/// Reads piped input, stopping just past `limit` so that oversized input fails the budget check
+/// without being read completely. Nothing is piped into a terminal, so it is refused rather than
+/// waited on.
+fn read_stdin(limit: u64, span: lsp_types::Range) -> Result<String, LakonikError> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Err(LakonikError::Invalid {
+            message: "`-` and `@-` read piped input, but nothing is piped into lakonik".to_string(),
+            span: Some(span),
+        });
     }
 
-    Ok(())
+    let mut bytes = Vec::new();
+    stdin
+        .take(limit + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|source| LakonikError::Io {
+            path: PathBuf::from("<stdin>"),
+            source,
+        })?;
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
 }
 
-async fn cmd_eval(verbose: bool, input: &[String]) -> Result<()> {
+async fn cmd_eval(
+    verbose: bool,
+    mut inputs: PromptInputs,
+    no_persist: bool,
+    policy: &ShellPolicy,
+    input: &[String],
+) -> ExitCode {
     if verbose {
         eprintln!("Running in verbose mode...");
     }
     let raw = input.join(" ");
-    let prompt_builder_result = run_prompt_builder(&raw);
+    if let Some(span) = engine::parse(&raw, &inputs.defaults)
+        .ok()
+        .and_then(|sentence| stdin_span(&sentence))
+    {
+        match read_stdin(inputs.max_attachment_bytes, span) {
+            Ok(stdin) => inputs.stdin = Some(stdin),
+            Err(err) => {
+                eprintln!("{}", err.report(&raw));
+                return ExitCode::from(err.exit_code());
+            }
+        }
+    }
+    let prompt_builder_result = match run_prompt_builder(&raw, &inputs, policy).await {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}", err.report(&raw));
+            return ExitCode::from(err.exit_code());
+        }
+    };
+    for result in &prompt_builder_result.shell_results {
+        if verbose {
+            eprintln!("`{}` took {:.2?}", result.code, result.duration);
+        }
+        // Omitted failures are left out on purpose, aborted ones never get here
+        if !result.output.success() && policy.on_failure == OnFailure::Warn {
+            let failure = ShellError::Failed {
+                code: result.code.clone(),
+                output: result.output.clone(),
+            };
+            eprintln!("warning: {failure}");
+        }
+    }
+    if !no_persist {
+        for write in &prompt_builder_result.pending_writes {
+            if let Err(err) = write.commit() {
+                eprintln!("{}", err.report(&raw));
+                return ExitCode::from(err.exit_code());
+            }
+        }
+    }
     let json =
         serde_json::to_string(&prompt_builder_result).expect("Failed to serialize result to JSON");
 
     println!("{json}");
+    ExitCode::SUCCESS
+}
+
+async fn cmd_templates(command: &TemplatesCommand) -> ExitCode {
+    match command {
+        TemplatesCommand::Which { name } => {
+            let templates = templates::which_template(name);
+            if templates.is_empty() {
+                return report(Err(LakonikError::TemplateNotFound {
+                    name: name.clone(),
+                    span: None,
+                }));
+            }
+
+            for (i, template) in templates.iter().enumerate() {
+                let location = template.file_path().map_or_else(
+                    || format!("(embedded in lakonik {})", env!("CARGO_PKG_VERSION")),
+                    |path| path.display().to_string(),
+                );
+                let status = if i == 0 { "used" } else { "overridden" };
+                println!(
+                    "{}\t{}\t{location}\t{status}",
+                    template.path, template.source
+                );
+            }
+
+            ExitCode::SUCCESS
+        }
+        TemplatesCommand::List {} => {
+            for template in manage::list_templates() {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    template.path,
+                    template.template_type,
+                    template.source,
+                    template.description().unwrap_or_default()
+                );
+            }
+
+            ExitCode::SUCCESS
+        }
+        TemplatesCommand::Check {} => report(cmd_templates_check()),
+        TemplatesCommand::Test { update } => report(cmd_templates_test(*update).await),
+        TemplatesCommand::Show { name } => report(manage::resolve_template(name).map(|template| {
+            print!("{}", template.contents);
+        })),
+        TemplatesCommand::New { name, from, layer } => report(
+            manage::scaffold_template(name, from, layer.source())
+                .map(|path| println!("{}", path.display())),
+        ),
+        TemplatesCommand::Edit { name, layer } => {
+            report(manage::editable_template(name, layer.source()).and_then(|p| open_editor(&p)))
+        }
+        TemplatesCommand::Delete { name, layer } => report(
+            manage::delete_template(name, layer.source())
+                .map(|path| println!("deleted {}", path.display())),
+        ),
+        TemplatesCommand::Export { dest, names, layer } => report(
+            manage::export_templates(layer.source(), names, dest)
+                .map(|count| println!("exported {count} templates to {}", dest.display())),
+        ),
+        TemplatesCommand::Import { src, force, layer } => report(
+            manage::import_templates(src, layer.source(), *force).map(|paths| {
+                for path in paths {
+                    println!("imported {path}");
+                }
+            }),
+        ),
+    }
+}
+
+fn cmd_templates_check() -> Result<(), LakonikError> {
+    let in_use = manage::list_templates();
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for template in templates::get_all_templates() {
+        let location = template.file_path().map_or_else(
+            || format!("{}:///{}", lsp::BUILT_IN_SCHEME, template.path),
+            |path| path.display().to_string(),
+        );
+        for diagnostic in check::check_template(&template, &in_use) {
+            match diagnostic.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+            println!(
+                "{location}:{}: {}: {}",
+                diagnostic.line, diagnostic.severity, diagnostic.message
+            );
+        }
+    }
+
+    let summary = format!("{errors} errors, {warnings} warnings");
+    if errors > 0 {
+        return Err(LakonikError::Invalid {
+            message: summary,
+            span: None,
+        });
+    }
+
+    eprintln!("{summary}");
+    Ok(())
+}
+
+async fn cmd_templates_test(update: bool) -> Result<(), LakonikError> {
+    let mut files = fixtures::discover_fixtures()?;
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for file in &mut files {
+        let results = fixtures::run_fixture(file, update).await?;
+        for result in results {
+            let label = format!("{} `{}`", file.template, result.input);
+            if result.passed() {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+            match result.outcome {
+                CaseOutcome::Passed => println!("{label} ... ok"),
+                CaseOutcome::Updated => println!("{label} ... updated"),
+                CaseOutcome::Failed { diff } => println!("{label} ... FAILED\n{diff}"),
+                CaseOutcome::Error(err) => {
+                    println!("{label} ... FAILED\n{}", err.report(&result.input))
+                }
+            }
+        }
+    }
+
+    let summary = format!("{passed} passed, {failed} failed");
+    if failed > 0 {
+        return Err(LakonikError::Invalid {
+            message: summary,
+            span: None,
+        });
+    }
+
+    eprintln!("{summary}");
     Ok(())
 }
 
+fn cmd_config(command: &ConfigCommand) -> Result<(), LakonikError> {
+    if let ConfigCommand::Set {
+        key,
+        value,
+        project,
+    } = command
+    {
+        let layer = if *project {
+            ConfigLayer::Project
+        } else {
+            ConfigLayer::User
+        };
+        return config::set(layer, key, value).map(|path| println!("{}", path.display()));
+    }
+
+    let config = Config::load()?;
+    match command {
+        ConfigCommand::Get { key } => {
+            // API keys never live in `config.toml`, but scripts can still ask for them
+            if let Some(provider) = key
+                .strip_prefix("providers.")
+                .and_then(|rest| rest.strip_suffix(".api_key"))
+            {
+                let (key, _) =
+                    config
+                        .settings
+                        .api_key(provider)?
+                        .ok_or_else(|| LakonikError::Config {
+                            message: format!("there is no API key for `{provider}`"),
+                        })?;
+                println!("{key}");
+                return Ok(());
+            }
+
+            let value = config.get(key)?.ok_or_else(|| LakonikError::Config {
+                message: format!("`{key}` is not set"),
+            })?;
+            println!("{}", config::display_value(&value));
+            Ok(())
+        }
+        ConfigCommand::List {} => {
+            for (key, value, layer) in config.entries() {
+                println!("{key}\t{}\t{layer}", config::display_value(&value));
+            }
+            for provider in config.settings.providers.keys() {
+                if let Some((_, source)) = config.settings.api_key(provider)? {
+                    println!("providers.{provider}.api_key\t(hidden)\t{source}");
+                }
+            }
+
+            Ok(())
+        }
+        ConfigCommand::Set { .. } => unreachable!("handled above"),
+    }
+}
+
+fn report(result: Result<(), LakonikError>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+/// Runs `$VISUAL` or `$EDITOR`, which may include arguments, falling back to `vi`
+fn open_editor(path: &std::path::Path) -> Result<(), LakonikError> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let Some(program) = words.next() else {
+        return Err(LakonikError::Config {
+            message: "$EDITOR is empty".to_string(),
+        });
+    };
+
+    let source = match std::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+    {
+        Ok(status) if status.success() => return Ok(()),
+        Ok(status) => ShellError::Failed {
+            code: editor,
+            output: ShellOutput::from_status(status),
+        },
+        Err(source) => ShellError::Io {
+            code: editor,
+            source,
+        },
+    };
+
+    Err(LakonikError::Shell { source, span: None })
+}
+
 async fn cmd_lsp() {
     run_lsp_server().await;
 }
@@ -0,0 +1,438 @@
+pub mod policy;
+mod sandbox;
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+pub use policy::{Isolation, OnFailure, ShellPolicy};
+
+/// Variables that are still passed to commands when running with a minimal environment
+const MINIMAL_ENV: &[&str] = &["HOME", "LANG", "PATH", "TERM", "USER"];
+
+/// Why inline shell code was not run, or did not finish
+#[derive(Debug)]
+pub enum ShellError {
+    Denied { command: String, rule: String },
+    NotAllowed { command: String },
+    NotAnalyzable { code: String },
+    Declined { code: String },
+    TimedOut { code: String, timeout: Duration },
+    OutputTooLarge { code: String, limit: usize },
+    Failed { code: String, output: ShellOutput },
+    UnknownInterpreter { tag: String },
+    NotStubbed { code: String },
+    SandboxUnavailable,
+    Io { code: String, source: io::Error },
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::Denied { command, rule } => {
+                write!(
+                    f,
+                    "`{command}` is denied by the shell policy (rule `{rule}`)"
+                )
+            }
+            ShellError::NotAllowed { command } => {
+                write!(f, "`{command}` is not in the shell allowlist")
+            }
+            ShellError::NotAnalyzable { code } => write!(
+                f,
+                "`{code}` uses substitutions or redirections that the shell policy cannot check"
+            ),
+            ShellError::Declined { code } => write!(f, "running `{code}` was declined"),
+            ShellError::TimedOut { code, timeout } => {
+                write!(
+                    f,
+                    "`{code}` did not finish within {}s",
+                    timeout.as_secs_f32()
+                )
+            }
+            ShellError::OutputTooLarge { code, limit } => {
+                write!(f, "output of `{code}` exceeds the limit of {limit} bytes")
+            }
+            ShellError::Failed { code, output } => {
+                match output.exit_code {
+                    Some(exit_code) => write!(f, "`{code}` exited with status {exit_code}")?,
+                    None => match output.signal {
+                        Some(signal) => write!(f, "`{code}` was killed by signal {signal}")?,
+                        None => write!(f, "`{code}` was killed")?,
+                    },
+                }
+                match output.stderr.lines().next() {
+                    Some(line) => write!(f, ": {line}"),
+                    None => Ok(()),
+                }
+            }
+            ShellError::UnknownInterpreter { tag } => {
+                write!(f, "no interpreter is configured for `${tag}(...)`")
+            }
+            ShellError::NotStubbed { code } => write!(f, "no output is stubbed for `{code}`"),
+            ShellError::SandboxUnavailable => {
+                write!(
+                    f,
+                    "sandboxing requires `bwrap` (bubblewrap) to be installed"
+                )
+            }
+            ShellError::Io { code, source } => write!(f, "could not run `{code}`: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShellError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn command(tag: Option<&str>, code: &str, policy: &ShellPolicy) -> Result<Command, ShellError> {
+    let mut argv = policy.interpreter(tag)?.to_vec();
+    argv.push(code.to_string());
+
+    let mut command = match policy.isolation {
+        Isolation::None => {
+            let mut command = Command::new(&argv[0]);
+            command.args(&argv[1..]);
+            command
+        }
+        Isolation::Sandbox { read_only, network } => {
+            let bwrap = sandbox::find_bwrap().ok_or(ShellError::SandboxUnavailable)?;
+            let working_dir = match &policy.working_dir {
+                Some(dir) => dir.clone(),
+                None => std::env::current_dir().map_err(|source| ShellError::Io {
+                    code: code.to_string(),
+                    source,
+                })?,
+            };
+
+            let mut command = Command::new(bwrap);
+            command.args(sandbox::bwrap_args(read_only, network, &working_dir, &argv));
+            command
+        }
+    };
+
+    if let Some(dir) = &policy.working_dir {
+        command.current_dir(dir);
+    }
+
+    if policy.minimal_env {
+        let env = MINIMAL_ENV
+            .iter()
+            .filter_map(|name| Some((*name, std::env::var(name).ok()?)));
+        command.env_clear().envs(env);
+    }
+
+    command.stdin(Stdio::null()).kill_on_drop(true);
+
+    Ok(command)
+}
+
+/// Asks on the controlling terminal, so it works even when stdin and stdout are redirected
+fn confirm(code: &str) -> bool {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return false;
+    };
+    if write!(tty, "Run `{code}`? [y/N] ").is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+    if BufReader::new(tty).read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Checks the code against the policy and, in confirm mode, asks whether it may run.
+///
+/// This is separate from [`execute`] so that callers running several commands concurrently can
+/// ask all questions up front, one at a time.
+pub fn authorize(tag: Option<&str>, code: &str, policy: &ShellPolicy) -> Result<(), ShellError> {
+    // Stubbed code never runs, so there is nothing to allow or confirm
+    if policy.stubs.is_some() {
+        return Ok(());
+    }
+    policy.check_tagged(tag, code)?;
+
+    if policy.confirm && !confirm(code) {
+        return Err(ShellError::Declined {
+            code: code.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// What a command wrote and how it exited
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShellOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the command was terminated by a signal
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the command, where the platform tells
+    pub signal: Option<i32>,
+}
+
+impl ShellOutput {
+    /// The outcome of a command whose output went to the terminal instead
+    pub fn from_status(status: std::process::ExitStatus) -> Self {
+        Self {
+            exit_code: status.code(),
+            signal: signal(status),
+            ..Self::default()
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+async fn read_capped(reader: impl AsyncRead + Unpin, limit: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)
+        .await?;
+
+    if bytes.len() > limit {
+        return Err(io::Error::from(io::ErrorKind::FileTooLarge));
+    }
+
+    Ok(bytes)
+}
+
+/// Runs code that has already been authorized with the interpreter selected by `tag` and
+/// captures its output
+pub async fn execute(
+    tag: Option<&str>,
+    code: &str,
+    policy: &ShellPolicy,
+) -> Result<ShellOutput, ShellError> {
+    let io_error = |source| ShellError::Io {
+        code: code.to_string(),
+        source,
+    };
+    let limit = policy.max_output_bytes;
+
+    if let Some(stubs) = &policy.stubs {
+        return stubs
+            .get(code)
+            .map(|stdout| ShellOutput {
+                stdout: stdout.clone(),
+                stderr: String::new(),
+                exit_code: Some(0),
+                signal: None,
+            })
+            .ok_or_else(|| ShellError::NotStubbed {
+                code: code.to_string(),
+            });
+    }
+
+    let mut child = command(tag, code, policy)?
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(io_error)?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let run = async {
+        // Reading fails as soon as either stream overflows, which drops (and kills) the child
+        // instead of waiting for a command that may never stop writing
+        let (stdout, stderr) =
+            tokio::try_join!(read_capped(stdout, limit), read_capped(stderr, limit))?;
+        if stdout.len() + stderr.len() > limit {
+            return Err(io::Error::from(io::ErrorKind::FileTooLarge));
+        }
+
+        Ok((stdout, stderr, child.wait().await?))
+    };
+
+    match timeout(policy.timeout, run).await {
+        Err(_) => Err(ShellError::TimedOut {
+            code: code.to_string(),
+            timeout: policy.timeout,
+        }),
+        Ok(Err(source)) if source.kind() == io::ErrorKind::FileTooLarge => {
+            Err(ShellError::OutputTooLarge {
+                code: code.to_string(),
+                limit,
+            })
+        }
+        Ok(Err(source)) => Err(io_error(source)),
+        Ok(Ok((stdout, stderr, status))) => Ok(ShellOutput {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            exit_code: status.code(),
+            signal: signal(status),
+        }),
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Runs inline code under the given policy and captures its output
+pub async fn run(
+    tag: Option<&str>,
+    code: &str,
+    policy: &ShellPolicy,
+) -> Result<ShellOutput, ShellError> {
+    authorize(tag, code, policy)?;
+    execute(tag, code, policy).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stubs_replace_running_code() {
+        let policy = ShellPolicy {
+            deny: vec!["git".to_string()],
+            stubs: Some([("git diff".to_string(), "stubbed\n".to_string())].into()),
+            ..ShellPolicy::default()
+        };
+
+        let output = run(None, "git diff", &policy).await.unwrap();
+        assert_eq!(output.stdout, "stubbed\n");
+        assert!(matches!(
+            run(None, "echo hello", &policy).await,
+            Err(ShellError::NotStubbed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn runs_allowed_code() {
+        let output = run(None, "echo hello", &ShellPolicy::default())
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, "hello\n");
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    async fn captures_stderr_and_exit_status_separately() {
+        let output = run(None, "echo a; echo b >&2; exit 3", &ShellPolicy::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            output,
+            ShellOutput {
+                stdout: "a\n".to_string(),
+                stderr: "b\n".to_string(),
+                exit_code: Some(3),
+                signal: None,
+            }
+        );
+        assert!(!output.success());
+    }
+
+    #[tokio::test]
+    async fn runs_code_with_tagged_interpreter() {
+        let policy = ShellPolicy {
+            interpreters: [("awk".to_string(), vec!["awk".to_string()])].into(),
+            ..ShellPolicy::default()
+        };
+        let output = run(Some("awk"), "BEGIN { print 6 * 7 }", &policy)
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, "42\n");
+        assert!(matches!(
+            run(None, "echo hello", &policy).await,
+            Err(ShellError::UnknownInterpreter { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_policy_violations_without_running() {
+        let policy = ShellPolicy {
+            deny: vec!["touch".to_string()],
+            ..ShellPolicy::default()
+        };
+
+        assert!(matches!(
+            run(None, "touch should-not-exist", &policy).await,
+            Err(ShellError::Denied { .. })
+        ));
+        assert!(!std::path::Path::new("should-not-exist").exists());
+    }
+
+    #[tokio::test]
+    async fn stops_slow_commands() {
+        let policy = ShellPolicy {
+            timeout: Duration::from_millis(100),
+            ..ShellPolicy::default()
+        };
+
+        assert!(matches!(
+            run(None, "sleep 5", &policy).await,
+            Err(ShellError::TimedOut { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn caps_output() {
+        let policy = ShellPolicy {
+            max_output_bytes: 10,
+            ..ShellPolicy::default()
+        };
+
+        assert!(matches!(
+            run(None, "yes", &policy).await,
+            Err(ShellError::OutputTooLarge { limit: 10, .. })
+        ));
+        assert_eq!(
+            run(None, "echo 123456789", &policy).await.unwrap().stdout,
+            "123456789\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_working_dir_and_minimal_env() {
+        let tmp = tempfile::tempdir().unwrap();
+        let policy = ShellPolicy {
+            working_dir: Some(tmp.path().to_path_buf()),
+            minimal_env: true,
+            ..ShellPolicy::default()
+        };
+
+        let output = run(None, "pwd", &policy).await.unwrap().stdout;
+        let dir = tmp.path().canonicalize().unwrap();
+        assert_eq!(output, format!("{}\n", dir.display()));
+
+        // bash itself adds a few variables of its own
+        let allowed = [MINIMAL_ENV, &["PWD", "OLDPWD", "SHLVL", "_"]].concat();
+        let output = run(None, "env", &policy).await.unwrap().stdout;
+        for line in output.lines() {
+            let (name, _) = line.split_once('=').unwrap();
+            assert!(allowed.contains(&name), "unexpected variable `{name}`");
+        }
+    }
+}
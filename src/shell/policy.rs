@@ -0,0 +1,596 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::ShellError;
+
+/// How strongly inline shell code is isolated from the host
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Isolation {
+    /// Run directly on the host
+    #[default]
+    None,
+    /// Run inside a bubblewrap sandbox
+    Sandbox { read_only: bool, network: bool },
+}
+
+/// What to do with a command that exits with a non-zero status, ordered from the strictest
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Stop building the prompt
+    Abort,
+    /// Keep the output in the prompt, but report the failure
+    #[default]
+    Warn,
+    /// Leave the output out of the prompt
+    Omit,
+}
+
+/// Interpreter used by inline code without a tag, as in `$(...)`
+pub const DEFAULT_INTERPRETER: &str = "bash";
+
+/// Interpreters available out of the box, keyed by the tag that selects them (`$py(...)`)
+fn default_interpreters() -> BTreeMap<String, Vec<String>> {
+    [
+        ("bash", ["bash", "-c"]),
+        ("sh", ["sh", "-c"]),
+        ("py", ["python3", "-c"]),
+        ("nu", ["nu", "-c"]),
+    ]
+    .into_iter()
+    .map(|(tag, command)| (tag.to_string(), command.map(str::to_string).to_vec()))
+    .collect()
+}
+
+/// Programs whose code can be split into commands and checked against the allow and deny lists
+fn is_shell(interpreter: &[String]) -> bool {
+    interpreter.first().is_some_and(|program| {
+        let name = program.rsplit('/').next().unwrap_or(program);
+        matches!(name, "bash" | "dash" | "sh" | "zsh")
+    })
+}
+
+/// Rules that inline shell code has to follow before and while it runs
+#[derive(Clone, Debug)]
+pub struct ShellPolicy {
+    /// When set, every command of the code has to match one of these entries
+    pub allow: Option<Vec<String>>,
+    /// Commands that may never run. This is best-effort: a program can always start another
+    /// one in ways no list can foresee, so the sandbox and `allow` are the actual boundary.
+    pub deny: Vec<String>,
+    /// Options that no command may be given, matched by prefix so that `--output` also covers
+    /// `--output=FILE`
+    pub deny_options: Vec<String>,
+    /// Applies to each command on its own
+    pub timeout: Duration,
+    pub max_output_bytes: usize,
+    /// How many commands of a prompt may run at the same time
+    pub max_parallel: usize,
+    pub working_dir: Option<PathBuf>,
+    /// Only pass a handful of well-known variables to the command instead of the whole environment
+    pub minimal_env: bool,
+    pub isolation: Isolation,
+    /// Ask on the terminal before running anything
+    pub confirm: bool,
+    pub on_failure: OnFailure,
+    /// Command lines that run inline code, keyed by tag. The code is passed as the last argument.
+    pub interpreters: BTreeMap<String, Vec<String>>,
+    /// When set, nothing runs: code gets the output stubbed for it, keyed by the code as written
+    pub stubs: Option<BTreeMap<String, String>>,
+}
+
+impl Default for ShellPolicy {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            deny: Vec::new(),
+            deny_options: Vec::new(),
+            timeout: Duration::from_secs(30),
+            max_output_bytes: 1024 * 1024,
+            max_parallel: 4,
+            working_dir: None,
+            minimal_env: false,
+            isolation: Isolation::None,
+            confirm: false,
+            on_failure: OnFailure::default(),
+            interpreters: default_interpreters(),
+            stubs: None,
+        }
+    }
+}
+
+/// Splits code into the commands it runs, one list of words per pipeline stage or list item
+fn commands(code: &str) -> Vec<Vec<&str>> {
+    // Treat `&&` and `||` as single separators so they don't produce empty commands
+    code.trim()
+        .split("&&")
+        .flat_map(|part| part.split("||"))
+        .flat_map(split_stages)
+        .map(|stage| {
+            stage
+                .split_whitespace()
+                // Leading `NAME=value` pairs only set the environment of the command
+                .skip_while(|word| is_assignment(word))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Splits at `|`, `;`, `&` and newlines, but not at escaped ones such as the `\;` that ends
+/// `find -exec`
+fn split_stages(code: &str) -> Vec<&str> {
+    let mut stages = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in code.char_indices() {
+        if !escaped && matches!(c, '|' | ';' | '&' | '\n') {
+            stages.push(&code[start..i]);
+            start = i + c.len_utf8();
+        }
+        escaped = !escaped && c == '\\';
+    }
+    stages.push(&code[start..]);
+
+    stages
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.contains('=') && !word.starts_with('=')
+}
+
+/// Programs that run the command in their arguments, with their options that take a value and
+/// the number of arguments they take before the command, such as the duration of `timeout`
+const WRAPPERS: &[(&str, &[&str], usize)] = &[
+    ("busybox", &[], 0),
+    ("command", &[], 0),
+    ("env", &["-u", "-C", "--unset", "--chdir"], 0),
+    ("exec", &["-a"], 0),
+    ("ionice", &["-c", "-n", "--class", "--classdata"], 0),
+    ("nice", &["-n", "--adjustment"], 0),
+    ("nohup", &[], 0),
+    ("setsid", &[], 0),
+    (
+        "stdbuf",
+        &["-i", "-o", "-e", "--input", "--output", "--error"],
+        0,
+    ),
+    (
+        "sudo",
+        &[
+            "-u", "-g", "-C", "-D", "-h", "-p", "-r", "-t", "-U", "--user", "--group",
+        ],
+        0,
+    ),
+    ("time", &[], 0),
+    ("timeout", &["-s", "-k", "--signal", "--kill-after"], 1),
+    (
+        "xargs",
+        &[
+            "-a",
+            "-d",
+            "-E",
+            "-I",
+            "-L",
+            "-n",
+            "-P",
+            "-s",
+            "--arg-file",
+            "--delimiter",
+        ],
+        0,
+    ),
+];
+
+/// Options of `find` that run the command after them, up to a `;` or `+`
+const FIND_ACTIONS: &[&str] = &["-exec", "-execdir", "-ok", "-okdir"];
+
+/// Shell keywords that can stand before a command
+const KEYWORDS: &[&str] = &[
+    "!", "{", "}", "if", "then", "elif", "else", "while", "until", "do",
+];
+
+/// Programs that run code given as a string, which can't be checked word by word
+fn runs_code(command: &[String]) -> bool {
+    match command.first().map(String::as_str) {
+        Some("eval") => true,
+        Some("bash" | "dash" | "sh" | "zsh") => command.iter().any(|word| word == "-c"),
+        Some("env") => command
+            .iter()
+            .any(|word| word.starts_with("-S") || word.starts_with("--split-string")),
+        _ => false,
+    }
+}
+
+/// Drops the quotes, escapes and subshell parentheses that don't change which program runs
+fn unquote(word: &str) -> String {
+    word.chars()
+        .filter(|c| !matches!(c, '\'' | '"' | '\\' | '(' | ')'))
+        .collect()
+}
+
+fn basename(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+/// What a command runs: the command itself and, for wrappers such as `env rm foo`, each command
+/// they wrap. Programs are reduced to their basename, so `/bin/rm` is `rm`.
+fn invocations(command: &[&str]) -> Vec<Vec<String>> {
+    let mut words = command
+        .iter()
+        .map(|word| unquote(word))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let mut invocations = Vec::new();
+
+    loop {
+        let keywords = words
+            .iter()
+            .take_while(|word| KEYWORDS.contains(&word.as_str()))
+            .count();
+        words.drain(..keywords);
+        let Some(program) = words.first_mut() else {
+            break;
+        };
+        *program = basename(program).to_string();
+        invocations.push(words.clone());
+
+        if words[0] == "find" {
+            let mut rest = words.as_slice();
+            while let Some(start) = rest.iter().position(|w| FIND_ACTIONS.contains(&w.as_str())) {
+                let action = &rest[start + 1..];
+                let end = action
+                    .iter()
+                    .position(|w| w == ";" || w == "+")
+                    .unwrap_or(action.len());
+                let action_words = action[..end].iter().map(String::as_str).collect::<Vec<_>>();
+                invocations.extend(self::invocations(&action_words));
+                rest = &action[end..];
+            }
+            break;
+        }
+        let Some((_, options, arguments)) = WRAPPERS.iter().find(|(name, ..)| *name == words[0])
+        else {
+            break;
+        };
+        // Skip the wrapper with its options and assignments to get to the wrapped command
+        let mut skip = 1;
+        while let Some(word) = words.get(skip) {
+            skip += match word.as_str() {
+                option if options.contains(&option) => 2,
+                option if option.starts_with('-') || is_assignment(option) => 1,
+                _ => break,
+            };
+        }
+        skip += arguments;
+        words.drain(..skip.min(words.len()));
+    }
+
+    invocations
+}
+
+/// An entry such as `git diff` matches any command that starts with the same words
+fn matches_entry(command: &[&str], entry: &str) -> bool {
+    let entry = entry.split_whitespace().collect::<Vec<_>>();
+
+    !entry.is_empty() && command.starts_with(&entry)
+}
+
+/// Like [`matches_entry`], but on the basename of the program, so `rm` also matches `/bin/rm`
+fn matches_program(command: &[String], entry: &str) -> bool {
+    let mut entry = entry.split_whitespace();
+    let Some(program) = entry.next() else {
+        return false;
+    };
+
+    command
+        .first()
+        .is_some_and(|first| first == basename(program))
+        && entry
+            .enumerate()
+            .all(|(i, word)| command.get(i + 1).is_some_and(|w| w == word))
+}
+
+impl ShellPolicy {
+    /// Only allows commands that read state, used to preview output without side effects.
+    /// Options that make them write, such as `git diff --output`, are denied.
+    pub fn read_only_preview() -> Self {
+        let allow = [
+            "cat",
+            "echo",
+            "expr",
+            "grep",
+            "head",
+            "ls",
+            "pwd",
+            "tail",
+            "uname",
+            "wc",
+            "whoami",
+            "git diff",
+            "git log",
+            "git show",
+            "git status",
+        ];
+
+        Self {
+            allow: Some(allow.iter().map(|c| c.to_string()).collect()),
+            deny_options: vec!["--output".to_string()],
+            timeout: Duration::from_secs(2),
+            ..Self::default()
+        }
+    }
+
+    /// The command line for the interpreter selected by `tag`, or the default one without a tag
+    pub fn interpreter(&self, tag: Option<&str>) -> Result<&[String], ShellError> {
+        let tag = tag.unwrap_or(DEFAULT_INTERPRETER);
+
+        self.interpreters
+            .get(tag)
+            .filter(|command| !command.is_empty())
+            .map(Vec::as_slice)
+            .ok_or_else(|| ShellError::UnknownInterpreter {
+                tag: tag.to_string(),
+            })
+    }
+
+    /// Checks code for the interpreter selected by `tag` without running it
+    pub fn check_tagged(&self, tag: Option<&str>, code: &str) -> Result<(), ShellError> {
+        let interpreter = self.interpreter(tag)?;
+        if is_shell(interpreter) {
+            return self.check(code);
+        }
+
+        // Code in other languages can't be split into commands, so the rules apply to the
+        // interpreter as a whole
+        let command = interpreter.iter().map(String::as_str).collect::<Vec<_>>();
+        self.check_denied(&command)?;
+
+        match &self.allow {
+            Some(allow) if !allow.iter().any(|e| matches_entry(&command, e)) => {
+                Err(ShellError::NotAllowed {
+                    command: command.join(" "),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if the command, or a command it wraps, matches an entry of the denylist
+    fn check_denied(&self, command: &[&str]) -> Result<(), ShellError> {
+        for word in command {
+            if let Some(option) = self.deny_options.iter().find(|o| word.starts_with(*o)) {
+                return Err(ShellError::Denied {
+                    command: command.join(" "),
+                    rule: option.clone(),
+                });
+            }
+        }
+        for invocation in invocations(command) {
+            if let Some(entry) = self.deny.iter().find(|e| matches_program(&invocation, e)) {
+                return Err(ShellError::Denied {
+                    command: command.join(" "),
+                    rule: entry.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks shell code against the allow and deny lists without running it
+    pub fn check(&self, code: &str) -> Result<(), ShellError> {
+        let commands = commands(code);
+
+        for command in commands.iter().filter(|c| !c.is_empty()) {
+            self.check_denied(command)?;
+        }
+
+        // Substitutions, redirections, code run from strings or variables and program names
+        // that the shell expands, such as `/bin/r?` or `{rm,x}`, can hide a denied command, so
+        // a denylist cannot vouch for them either
+        if !self.deny.is_empty()
+            && (code.contains(['`', '<', '>'])
+                || code.contains("$(")
+                || commands.iter().any(|command| {
+                    let invocations = invocations(command);
+                    invocations.is_empty()
+                        || invocations
+                            .iter()
+                            .any(|i| runs_code(i) || i[0].contains(['$', '*', '?', '[', '{']))
+                }))
+        {
+            return Err(ShellError::NotAnalyzable {
+                code: code.to_string(),
+            });
+        }
+
+        let Some(allow) = &self.allow else {
+            return Ok(());
+        };
+
+        // Substitutions and redirections can run or touch anything, so an allowlist cannot
+        // vouch for them
+        if code.contains(['`', '$', '<', '>']) || commands.iter().any(Vec::is_empty) {
+            return Err(ShellError::NotAnalyzable {
+                code: code.to_string(),
+            });
+        }
+
+        match commands
+            .iter()
+            .find(|command| !allow.iter().any(|e| matches_entry(command, e)))
+        {
+            Some(command) => Err(ShellError::NotAllowed {
+                command: command.join(" "),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn policy(allow: Option<&[&str]>, deny: &[&str]) -> ShellPolicy {
+        ShellPolicy {
+            allow: allow.map(|a| a.iter().map(|c| c.to_string()).collect()),
+            deny: deny.iter().map(|c| c.to_string()).collect(),
+            ..ShellPolicy::default()
+        }
+    }
+
+    #[rstest]
+    #[case("rm -rf /tmp/foo", "rm")]
+    #[case("ls && rm foo", "rm")]
+    #[case("ls | FOO=bar rm foo", "rm")]
+    #[case("git push origin", "git push")]
+    #[case("/bin/rm x", "rm")]
+    #[case("command rm x", "rm")]
+    #[case("env FOO=bar -u HOME rm x", "rm")]
+    #[case("exec -a name rm x", "rm")]
+    #[case("nice -n 10 rm x", "rm")]
+    #[case("find . | xargs -I {} rm {}", "rm")]
+    #[case("sudo -u root /usr/bin/rm x", "rm")]
+    #[case("sudo env rm x", "rm")]
+    #[case("\\rm x", "rm")]
+    #[case("\"rm\" x", "rm")]
+    #[case("(rm x)", "rm")]
+    #[case("if true; then rm x; fi", "rm")]
+    #[case("/usr/bin/git push", "git push")]
+    #[case("timeout 5 rm x", "rm")]
+    #[case("timeout -s KILL 5 rm x", "rm")]
+    #[case("stdbuf -o L rm x", "rm")]
+    #[case("setsid rm x", "rm")]
+    #[case("ionice -c 3 rm x", "rm")]
+    #[case("busybox rm x", "rm")]
+    #[case("find . -name '*.tmp' -exec rm {} \\;", "rm")]
+    #[case("find . -exec ls \\; -execdir rm {} +", "rm")]
+    fn denied_commands(#[case] code: &str, #[case] rule: &str) {
+        let result = policy(None, &["rm", "git push"]).check(code);
+
+        assert!(
+            matches!(&result, Err(ShellError::Denied { rule: r, .. }) if r == rule),
+            "{result:?}"
+        );
+    }
+
+    #[rstest]
+    #[case("git status")]
+    #[case("git diff | grep foo")]
+    #[case("echo foo\nls")]
+    #[case("ls && echo foo || echo bar")]
+    fn allowed_commands(#[case] code: &str) {
+        assert!(
+            policy(
+                Some(&["ls", "echo", "grep", "git status", "git diff"]),
+                &["rm"]
+            )
+            .check(code)
+            .is_ok()
+        );
+    }
+
+    #[rstest]
+    #[case("git push")]
+    #[case("ls | wc -l")]
+    fn commands_outside_of_allowlist(#[case] code: &str) {
+        assert!(matches!(
+            policy(Some(&["ls", "git status"]), &[]).check(code),
+            Err(ShellError::NotAllowed { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("echo $(rm foo)")]
+    #[case("echo `rm foo`")]
+    #[case("ls > foo")]
+    #[case("ls |")]
+    fn unanalyzable_code_with_allowlist(#[case] code: &str) {
+        assert!(matches!(
+            policy(Some(&["ls", "echo"]), &[]).check(code),
+            Err(ShellError::NotAnalyzable { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("echo $(rm x)")]
+    #[case("echo `rm x`")]
+    #[case("ls > x")]
+    #[case("cat < x")]
+    #[case("ls |")]
+    #[case("eval 'rm x'")]
+    #[case("bash -c 'rm x'")]
+    #[case("X=rm; $X x")]
+    #[case("env -S\"rm x\"")]
+    #[case("env --split-string='rm x'")]
+    #[case("{rm,x}")]
+    #[case("/bin/r? x")]
+    #[case("/bin/r[m] x")]
+    #[case("timeout 5 /bin/r* x")]
+    #[case("find . -exec /bin/r? {} +")]
+    fn unanalyzable_code_with_denylist(#[case] code: &str) {
+        assert!(matches!(
+            policy(None, &["rm"]).check(code),
+            Err(ShellError::NotAnalyzable { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("git status")]
+    #[case("echo $HOME | grep root")]
+    #[case("\nls\n")]
+    #[case("git diff --stat")]
+    #[case("find . -name '*.rs' -exec grep -l foo {} +")]
+    #[case("ls *.rs")]
+    fn analyzable_code_with_denylist(#[case] code: &str) {
+        assert!(policy(None, &["rm"]).check(code).is_ok());
+    }
+
+    #[rstest]
+    #[case(Some("py"), Some(&["python3"][..]), &[][..], true)]
+    #[case(Some("py"), Some(&["ls"][..]), &[][..], false)]
+    #[case(Some("py"), None, &["python3"][..], false)]
+    #[case(Some("sh"), Some(&["ls"][..]), &[][..], true)]
+    #[case(None, None, &["ls"][..], false)]
+    fn rules_for_other_interpreters(
+        #[case] tag: Option<&str>,
+        #[case] allow: Option<&[&str]>,
+        #[case] deny: &[&str],
+        #[case] expected: bool,
+    ) {
+        assert_eq!(
+            policy(allow, deny).check_tagged(tag, "ls").is_ok(),
+            expected
+        );
+    }
+
+    #[test]
+    fn unknown_interpreters() {
+        assert!(matches!(
+            ShellPolicy::default().check_tagged(Some("cobol"), "ls"),
+            Err(ShellError::UnknownInterpreter { tag }) if tag == "cobol"
+        ));
+    }
+
+    #[test]
+    fn no_restrictions_by_default() {
+        assert!(ShellPolicy::default().check("echo $(rm foo) > bar").is_ok());
+    }
+}
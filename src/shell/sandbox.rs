@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+/// Looks up the bubblewrap executable on the `PATH`
+pub fn find_bwrap() -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join("bwrap"))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// Arguments for `bwrap` that run `command` with a private `/tmp`, optionally without write
+/// access to the host and without network access
+pub fn bwrap_args(
+    read_only: bool,
+    network: bool,
+    working_dir: &Path,
+    command: &[String],
+) -> Vec<String> {
+    let root_bind = if read_only { "--ro-bind" } else { "--bind" };
+    let mut args = vec![
+        "--die-with-parent",
+        "--new-session",
+        root_bind,
+        "/",
+        "/",
+        "--dev",
+        "/dev",
+        "--proc",
+        "/proc",
+        "--tmpfs",
+        "/tmp",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+
+    if !network {
+        args.push("--unshare-net".to_string());
+    }
+
+    args.extend([
+        "--chdir".to_string(),
+        working_dir.to_string_lossy().into_owned(),
+    ]);
+    args.extend_from_slice(command);
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_without_network() {
+        let command = ["bash", "-c", "ls"].map(str::to_string);
+
+        let args = bwrap_args(false, true, Path::new("/work"), &command);
+        assert!(args.contains(&"--bind".to_string()));
+        assert!(!args.contains(&"--unshare-net".to_string()));
+
+        let args = bwrap_args(true, false, Path::new("/work"), &command);
+        assert!(args.contains(&"--ro-bind".to_string()));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert!(args.ends_with(&[
+            "--chdir".to_string(),
+            "/work".to_string(),
+            "bash".to_string(),
+            "-c".to_string(),
+            "ls".to_string(),
+        ]));
+    }
+}
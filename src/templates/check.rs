@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use minijinja::Environment;
+
+use super::functions::{self, TemplatePolicy};
+use super::metadata::{TemplateMetadata, split_front_matter};
+use super::{Template, TemplateType};
+use crate::ast::is_valid_name;
+use crate::context::RenderContext;
+
+/// Statements that refer to another template by name
+const REFERENCE_KEYWORDS: [&str; 4] = ["extends", "include", "import", "from"];
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// The template can't be rendered
+    Error,
+    /// The template renders, but probably not the way it was meant to
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// A problem found in a template, on a line of its file counted from 1, front-matter included
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateDiagnostic {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl TemplateDiagnostic {
+    fn error(line: usize, message: String) -> Self {
+        TemplateDiagnostic {
+            line,
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(line: usize, message: String) -> Self {
+        TemplateDiagnostic {
+            line,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Variables the engine renders a template with, or `None` if that is up to the caller, as for
+/// parts rendered through `include_part`. These follow `engine` and `context::RenderContext`.
+fn known_variables(template: &Template) -> Option<Vec<String>> {
+    let names: &[&str] = match (template.template_type, template.path.as_str()) {
+        (TemplateType::Verb, _) => return Some(RenderContext::variables()),
+        (TemplateType::Vocative | TemplateType::System, _) => &["vocative", "verb", "output"],
+        (_, "parts/shell") => &[
+            "interpreter",
+            "code",
+            "stdout",
+            "stderr",
+            "exit_code",
+            "signal",
+            "result",
+        ],
+        (_, "parts/stdin") => &["content", "bytes"],
+        _ => return None,
+    };
+
+    Some(names.iter().map(|name| name.to_string()).collect())
+}
+
+/// Byte offsets of `word` in `text` where it is not part of a longer identifier
+fn word_offsets<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    text.match_indices(word)
+        .map(|(offset, _)| offset)
+        .filter(move |&offset| {
+            let before = text[..offset].chars().next_back();
+            let after = text[offset + word.len()..].chars().next();
+
+            !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+        })
+}
+
+/// The statement that starts at `offset`, with `{%`, whitespace control and `%}` removed
+fn statement_at(body: &str, offset: usize) -> &str {
+    let rest = &body[offset + 2..];
+    let end = rest.find("%}").unwrap_or(rest.len());
+
+    rest[..end]
+        .trim_start_matches(['-', '+'])
+        .trim_end_matches(['-', '+'])
+        .trim()
+}
+
+/// A reference to another template, such as `{% extends "verbs/base/base" %}`
+struct Reference {
+    name: String,
+    offset: usize,
+    /// `include ... ignore missing` is allowed to refer to a template that does not exist
+    optional: bool,
+}
+
+/// References to other templates by a literal name; names computed at render time are skipped
+fn references(body: &str) -> Vec<Reference> {
+    body.match_indices("{%")
+        .filter_map(|(offset, _)| {
+            let statement = statement_at(body, offset);
+            let rest = REFERENCE_KEYWORDS.iter().find_map(|keyword| {
+                statement
+                    .strip_prefix(keyword)
+                    .filter(|rest| rest.starts_with(char::is_whitespace))
+            })?;
+            let rest = rest.trim_start();
+            let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+            let end = rest[1..].find(quote)?;
+
+            Some(Reference {
+                name: rest[1..=end].to_string(),
+                offset,
+                optional: rest[end + 2..].contains("ignore missing"),
+            })
+        })
+        .collect()
+}
+
+/// Follows references from `start` and returns the first chain that leads back to it
+fn find_cycle(start: &str, graph: &BTreeMap<String, Vec<String>>) -> Option<Vec<String>> {
+    fn visit(
+        graph: &BTreeMap<String, Vec<String>>,
+        chain: &mut Vec<String>,
+        visited: &mut BTreeSet<String>,
+    ) -> Option<Vec<String>> {
+        let node = chain.last()?.clone();
+        for next in graph.get(&node).into_iter().flatten() {
+            chain.push(next.clone());
+            if *next == chain[0] {
+                return Some(chain.clone());
+            }
+            if visited.insert(next.clone())
+                && let Some(cycle) = visit(graph, chain, visited)
+            {
+                return Some(cycle);
+            }
+            chain.pop();
+        }
+
+        None
+    }
+
+    visit(graph, &mut vec![start.to_string()], &mut BTreeSet::new())
+}
+
+fn check_front_matter(template: &Template, diagnostics: &mut Vec<TemplateDiagnostic>) {
+    let Some(front_matter) = split_front_matter(&template.contents).0 else {
+        return;
+    };
+    // Front-matter starts on the line after the opening `+++`
+    let line_at = |offset: usize| 2 + front_matter[..offset].matches('\n').count();
+    let key_line = |key: &str| word_offsets(front_matter, key).next().map_or(1, &line_at);
+
+    let metadata = match toml::from_str::<TemplateMetadata>(front_matter) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            let line = err.span().map_or(1, |span| line_at(span.start));
+            diagnostics.push(TemplateDiagnostic::error(
+                line,
+                format!("invalid front-matter: {}", err.message()),
+            ));
+            return;
+        }
+    };
+
+    for alias in metadata.aliases.iter().filter(|a| !is_valid_name(a)) {
+        diagnostics.push(TemplateDiagnostic::error(
+            key_line("aliases"),
+            format!("`{alias}` is not a valid verb name"),
+        ));
+    }
+    if let Some(vocative) = metadata
+        .default_vocative
+        .as_ref()
+        .filter(|v| !is_valid_name(v))
+    {
+        diagnostics.push(TemplateDiagnostic::error(
+            key_line("default-vocative"),
+            format!("`{vocative}` is not a valid vocative name"),
+        ));
+    }
+}
+
+/// Checks a template against `templates`, the templates in use that it can refer to. Errors
+/// keep the template from rendering, warnings point at variables that are probably mistakes.
+pub fn check_template(template: &Template, templates: &[Template]) -> Vec<TemplateDiagnostic> {
+    let mut diagnostics = Vec::new();
+    check_front_matter(template, &mut diagnostics);
+
+    let body = template.body();
+    let first_line = 1 + template.contents[..template.contents.len() - body.len()]
+        .matches('\n')
+        .count();
+    let line_at = |offset: usize| first_line + body[..offset].matches('\n').count();
+
+    let mut env = Environment::new();
+    functions::register(&mut env, &TemplatePolicy::default());
+    let compiled = match env.template_from_named_str(&template.path, body) {
+        Ok(compiled) => compiled,
+        Err(err) => {
+            let message = match err.detail() {
+                Some(detail) => format!("{}: {detail}", err.kind()),
+                None => err.kind().to_string(),
+            };
+            let line = first_line + err.line().unwrap_or(1) - 1;
+            diagnostics.push(TemplateDiagnostic::error(line, message));
+            return diagnostics;
+        }
+    };
+
+    // The template being checked takes the place of the one in use with the same path
+    let mut graph = templates
+        .iter()
+        .filter(|t| t.path != template.path)
+        .map(|t| {
+            let names = references(t.body()).into_iter().map(|r| r.name);
+            (t.path.clone(), names.collect::<Vec<_>>())
+        })
+        .collect::<BTreeMap<_, _>>();
+    let own_references = references(body);
+    graph.insert(
+        template.path.clone(),
+        own_references.iter().map(|r| r.name.clone()).collect(),
+    );
+
+    for reference in &own_references {
+        if !reference.optional && !graph.contains_key(&reference.name) {
+            diagnostics.push(TemplateDiagnostic::error(
+                line_at(reference.offset),
+                format!("template `{}` does not exist", reference.name),
+            ));
+        }
+    }
+    if let Some(cycle) = find_cycle(&template.path, &graph) {
+        let offset = own_references
+            .iter()
+            .find(|r| r.name == cycle[1])
+            .map_or(0, |r| r.offset);
+        diagnostics.push(TemplateDiagnostic::error(
+            line_at(offset),
+            format!("templates refer to each other: {}", cycle.join(" -> ")),
+        ));
+    }
+
+    if let Some(known) = known_variables(template) {
+        let globals = env
+            .globals()
+            .map(|(name, _)| name.to_string())
+            .collect::<BTreeSet<_>>();
+        let undefined = compiled
+            .undeclared_variables(false)
+            .into_iter()
+            .filter(|name| !known.contains(name) && !globals.contains(name))
+            .collect::<BTreeSet<_>>();
+
+        for name in undefined {
+            let offset = word_offsets(body, &name).next().unwrap_or_default();
+            diagnostics.push(TemplateDiagnostic::warning(
+                line_at(offset),
+                format!(
+                    "`{name}` is not passed to {} templates",
+                    template.template_type
+                ),
+            ));
+        }
+    }
+
+    for (offset, _) in body.match_indices("{%") {
+        let Some(name) = statement_at(body, offset)
+            .strip_prefix("set ")
+            .and_then(|rest| rest.split(['=', ' ']).find(|word| !word.is_empty()))
+        else {
+            continue;
+        };
+
+        if word_offsets(body, name).count() == 1 {
+            diagnostics.push(TemplateDiagnostic::warning(
+                line_at(offset),
+                format!("`{name}` is set but never used"),
+            ));
+        }
+    }
+
+    diagnostics.sort_by_key(|d| (d.line, d.severity));
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TemplateSource;
+    use rstest::rstest;
+
+    fn template(path: &str, contents: &str) -> Template {
+        Template::new(path.to_string(), contents.to_string(), TemplateSource::User)
+    }
+
+    fn check(contents: &str, others: &[(&str, &str)]) -> Vec<(usize, Severity, String)> {
+        let others = others
+            .iter()
+            .map(|(path, contents)| template(path, contents))
+            .collect::<Vec<_>>();
+
+        check_template(&template("verbs/checked", contents), &others)
+            .into_iter()
+            .map(|d| (d.line, d.severity, d.message))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("{% extends \"verbs/base\" %}{% block body %}{{ description }}{% endblock %}")]
+    #[case("{{ vocative }} {{ attachments | length }} {{ read_file('x') }} {{ range(3) }}")]
+    #[case("{% set x = 1 %}{{ x }} {% include \"parts/missing\" ignore missing %}")]
+    #[case("+++\ndescription = \"ok\"\n+++\n{{ description }}")]
+    fn clean_templates(#[case] contents: &str) {
+        assert_eq!(
+            check(
+                contents,
+                &[("verbs/base", "{% block body %}{% endblock %}")]
+            ),
+            vec![]
+        );
+    }
+
+    #[rstest]
+    #[case("+++\ndescription = \"ok\"\n+++\n\n{% if %}", 5, "syntax error")]
+    #[case(
+        "+++\ndescription = \"ok\"\nnope = 1\n+++\n",
+        3,
+        "invalid front-matter"
+    )]
+    #[case(
+        "+++\naliases = [\"Bad!\"]\n+++\n",
+        2,
+        "`Bad!` is not a valid verb name"
+    )]
+    #[case(
+        "\n{% extends \"verbs/nope\" %}",
+        2,
+        "template `verbs/nope` does not exist"
+    )]
+    #[case(
+        "{% extends \"verbs/other\" %}",
+        1,
+        "templates refer to each other: verbs/checked -> verbs/other -> verbs/checked"
+    )]
+    fn errors(#[case] contents: &str, #[case] line: usize, #[case] message: &str) {
+        let diagnostics = check(
+            contents,
+            &[("verbs/other", "{% include \"verbs/checked\" %}")],
+        );
+
+        assert_eq!(diagnostics[0].0, line);
+        assert_eq!(diagnostics[0].1, Severity::Error);
+        assert!(
+            diagnostics[0].2.starts_with(message),
+            "{:?} does not start with {message:?}",
+            diagnostics[0].2
+        );
+    }
+
+    #[test]
+    fn warnings() {
+        let diagnostics = check(
+            "{{ description }}\n{{ descripton }}\n{% set unused = 1 %}",
+            &[],
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    2,
+                    Severity::Warning,
+                    "`descripton` is not passed to verb templates".to_string()
+                ),
+                (
+                    3,
+                    Severity::Warning,
+                    "`unused` is set but never used".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn built_in_templates_are_clean() {
+        let templates = crate::templates::get_built_in_templates().collect::<Vec<_>>();
+
+        for template in &templates {
+            assert_eq!(
+                check_template(template, &templates),
+                vec![],
+                "{}",
+                template.path
+            );
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use walkdir::WalkDir;
+
+use super::manage::{config_error, io_error};
+use super::{project_template_dir, user_template_dir};
+use crate::context::FixtureContext;
+use crate::engine::{Defaults, PromptInputs, run_prompt_builder};
+use crate::error::LakonikError;
+use crate::shell::ShellPolicy;
+
+/// Fixtures sit next to the template they test, e.g. `verbs/review.test.toml` for `verbs/review`
+pub const FIXTURE_SUFFIX: &str = ".test.toml";
+
+/// Whether a file in a template directory is a fixture rather than a template
+pub fn is_fixture(path: &str) -> bool {
+    path.ends_with(FIXTURE_SUFFIX)
+}
+
+/// The cases of one fixture file
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Fixture {
+    /// What the cases may leave out. The configured `default_vocative` and `default_verb` are
+    /// never used, so that a fixture renders the same on every machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_vocative: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_verb: Option<String>,
+    /// Pins the working directory, branch, date, operating system and the files templates read
+    #[serde(default)]
+    pub context: FixtureContext,
+    #[serde(default, rename = "case")]
+    pub cases: Vec<FixtureCase>,
+}
+
+impl Fixture {
+    pub fn defaults(&self) -> Defaults {
+        Defaults {
+            vocative: self.default_vocative.clone(),
+            verb: self.default_verb.clone(),
+        }
+    }
+}
+
+/// A sentence and the prompt it is expected to render to
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FixtureCase {
+    pub input: String,
+    /// Piped input for `-` and `@-` parts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// The rendered prompt, written by `lakonik templates test --update`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// Output of the shell parts, keyed by their code. Nothing is run, and code without an
+    /// entry fails the case.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shell: BTreeMap<String, String>,
+}
+
+/// A fixture file and the template it sits next to
+#[derive(Debug)]
+pub struct FixtureFile {
+    pub path: PathBuf,
+    pub template: String,
+    pub fixture: Fixture,
+}
+
+#[derive(Debug)]
+pub enum CaseOutcome {
+    Passed,
+    /// The prompt differs from the expectation, shown as a unified diff
+    Failed {
+        diff: String,
+    },
+    /// The expectation was missing or differed, and has been rewritten
+    Updated,
+    Error(LakonikError),
+}
+
+#[derive(Debug)]
+pub struct CaseResult {
+    pub input: String,
+    pub outcome: CaseOutcome,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, CaseOutcome::Passed | CaseOutcome::Updated)
+    }
+}
+
+/// Every fixture in `dir`, sorted by path
+pub fn fixtures_in(dir: &Path) -> Result<Vec<FixtureFile>, LakonikError> {
+    let mut files = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| is_fixture(&e.file_name().to_string_lossy()))
+        .map(|entry| read_fixture(dir, entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(files)
+}
+
+fn read_fixture(root: &Path, path: &Path) -> Result<FixtureFile, LakonikError> {
+    let contents = fs::read_to_string(path).map_err(io_error(path))?;
+    let fixture = toml::from_str(&contents)
+        .map_err(|err| config_error(format!("invalid fixture `{}`: {err}", path.display())))?;
+    let rel = path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/");
+
+    Ok(FixtureFile {
+        path: path.to_path_buf(),
+        template: rel.trim_end_matches(FIXTURE_SUFFIX).to_string(),
+        fixture,
+    })
+}
+
+/// The fixtures of the project and user template directories
+pub fn discover_fixtures() -> Result<Vec<FixtureFile>, LakonikError> {
+    let mut files = Vec::new();
+    for dir in [project_template_dir(), user_template_dir()]
+        .into_iter()
+        .flatten()
+    {
+        files.extend(fixtures_in(&dir)?);
+    }
+
+    Ok(files)
+}
+
+fn diff(expected: &str, actual: &str) -> String {
+    TextDiff::from_lines(expected, actual)
+        .unified_diff()
+        .header("expected", "actual")
+        .to_string()
+}
+
+async fn run_case(case: &mut FixtureCase, update: bool, fixture: &Fixture) -> CaseOutcome {
+    let inputs = PromptInputs {
+        stdin: case.stdin.clone(),
+        defaults: fixture.defaults(),
+        fixture: Some(fixture.context.clone()),
+        ..PromptInputs::default()
+    };
+    let policy = ShellPolicy {
+        stubs: Some(case.shell.clone()),
+        ..ShellPolicy::default()
+    };
+    let prompt = match run_prompt_builder(&case.input, &inputs, &policy).await {
+        Ok(result) => result.prompt,
+        Err(err) => return CaseOutcome::Error(err),
+    };
+
+    match &case.expected {
+        Some(expected) if *expected == prompt => CaseOutcome::Passed,
+        _ if update => {
+            case.expected = Some(prompt);
+            CaseOutcome::Updated
+        }
+        Some(expected) => CaseOutcome::Failed {
+            diff: diff(expected, &prompt),
+        },
+        None => CaseOutcome::Failed {
+            diff: diff("", &prompt),
+        },
+    }
+}
+
+/// Writes the expectations of the cases at `updated` into the fixture file. Everything else,
+/// comments included, stays as it was written.
+fn write_expectations(
+    path: &Path,
+    cases: &[FixtureCase],
+    updated: &[usize],
+) -> Result<(), LakonikError> {
+    let invalid = |err: toml_edit::TomlError| {
+        config_error(format!("invalid fixture `{}`: {err}", path.display()))
+    };
+    let contents = fs::read_to_string(path).map_err(io_error(path))?;
+    let mut document = contents
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(invalid)?;
+
+    if let Some(tables) = document
+        .get_mut("case")
+        .and_then(toml_edit::Item::as_array_of_tables_mut)
+    {
+        for &i in updated {
+            if let (Some(table), Some(expected)) = (tables.get_mut(i), &cases[i].expected) {
+                table["expected"] = toml_edit::value(expected.as_str());
+            }
+        }
+    }
+
+    fs::write(path, document.to_string()).map_err(io_error(path))
+}
+
+/// Runs every case of a fixture. With `update`, expectations that are missing or differ are
+/// replaced by the actual prompt and written back to the file.
+pub async fn run_fixture(
+    file: &mut FixtureFile,
+    update: bool,
+) -> Result<Vec<CaseResult>, LakonikError> {
+    let mut cases = std::mem::take(&mut file.fixture.cases);
+    let mut results = Vec::new();
+    for case in &mut cases {
+        results.push(CaseResult {
+            input: case.input.clone(),
+            outcome: run_case(case, update, &file.fixture).await,
+        });
+    }
+    file.fixture.cases = cases;
+
+    let updated = results
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| matches!(r.outcome, CaseOutcome::Updated))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if !updated.is_empty() {
+        write_expectations(&file.path, &file.fixture.cases, &updated)?;
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::create_user_template;
+    use serial_test::serial;
+
+    const FIXTURE: &str = r#"
+[[case]]
+input = "robot ~testverbfixture:=(check {{ shell[0].stdout }}) $(git diff)"
+expected = "check stubbed\n"
+
+[case.shell]
+"git diff" = "stubbed\n"
+"#;
+
+    fn write_fixture(dir: &Path, contents: &str) -> PathBuf {
+        let path = dir.join("verbs/review.test.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn fixtures_pass_with_stubbed_shell_output() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path(), FIXTURE);
+
+        let mut files = fixtures_in(dir.path()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].template, "verbs/review");
+
+        let results = run_fixture(&mut files[0], false).await.unwrap();
+        assert!(results[0].passed(), "{:?}", results[0].outcome);
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn mismatches_are_shown_as_a_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path(), &FIXTURE.replace("check stubbed", "check other"));
+        let mut files = fixtures_in(dir.path()).unwrap();
+
+        let results = run_fixture(&mut files[0], false).await.unwrap();
+        let CaseOutcome::Failed { diff } = &results[0].outcome else {
+            panic!("expected a failure, got {:?}", results[0].outcome);
+        };
+        assert!(diff.contains("-check other\n"));
+        assert!(diff.contains("+check stubbed\n"));
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn update_rewrites_the_expectation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fixture(dir.path(), &FIXTURE.replace("expected = ", "# "));
+        let mut files = fixtures_in(dir.path()).unwrap();
+
+        let results = run_fixture(&mut files[0], true).await.unwrap();
+        assert!(matches!(results[0].outcome, CaseOutcome::Updated));
+
+        let mut files = fixtures_in(dir.path()).unwrap();
+        assert_eq!(
+            files[0].fixture.cases[0].expected.as_deref(),
+            Some("check stubbed\n")
+        );
+        assert!(run_fixture(&mut files[0], false).await.unwrap()[0].passed());
+        let contents = fs::read_to_string(path).unwrap();
+        assert!(contents.contains("# \"check stubbed\\n\""), "{contents}");
+        assert!(contents.contains("[case.shell]"), "{contents}");
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn unstubbed_shell_code_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(
+            dir.path(),
+            &FIXTURE.replace("\"git diff\" =", "\"git log\" ="),
+        );
+        let mut files = fixtures_in(dir.path()).unwrap();
+
+        let results = run_fixture(&mut files[0], false).await.unwrap();
+        assert!(matches!(results[0].outcome, CaseOutcome::Error(_)));
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn fixtures_declare_their_own_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(
+            dir.path(),
+            r#"
+default_verb = "create"
+
+[[case]]
+input = "robot create a parser"
+
+[[case]]
+input = "robot a parser"
+"#,
+        );
+        let mut files = fixtures_in(dir.path()).unwrap();
+
+        run_fixture(&mut files[0], true).await.unwrap();
+        let cases = &files[0].fixture.cases;
+        assert!(cases[0].expected.is_some());
+        assert_eq!(cases[0].expected, cases[1].expected);
+
+        files[0].fixture.default_verb = None;
+        let results = run_fixture(&mut files[0], false).await.unwrap();
+        assert!(results[0].passed(), "{:?}", results[0].outcome);
+        assert!(!results[1].passed(), "{:?}", results[1].outcome);
+    }
+
+    #[tokio::test]
+    #[serial(lakonik_config)]
+    async fn fixtures_render_the_same_everywhere() {
+        let _user_dir = crate::templates::TestUserDir::new();
+        create_user_template(
+            "verbs/testverbpinned",
+            "{{ cwd }} {{ git_branch }} {{ date }} {{ os }} {{ read_file('notes.txt') }} \
+             {{ git_diff() }}",
+        )
+        .unwrap();
+        create_user_template("verbs/testverbsaved", "saved").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(
+            dir.path(),
+            r#"
+[context]
+date = "2024-05-01"
+
+[context.files]
+"notes.txt" = "noted"
+
+[[case]]
+input = "robot testverbpinned"
+expected = "/project main 2024-05-01 linux noted diffed"
+
+[case.shell]
+"git diff" = "diffed"
+
+[[case]]
+input = "robot ~testverbsaved=(assigned) foo"
+expected = "assigned foo"
+"#,
+        );
+        let mut files = fixtures_in(dir.path()).unwrap();
+
+        let results = run_fixture(&mut files[0], false).await.unwrap();
+        for result in results {
+            assert!(result.passed(), "{:?}", result.outcome);
+        }
+    }
+}
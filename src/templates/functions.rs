@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use minijinja::value::Kwargs;
+use minijinja::{Environment, Error, ErrorKind, State, Value};
+
+use crate::engine::DEFAULT_MAX_ATTACHMENT_BYTES;
+use crate::shell::{self, ShellError, ShellOutput, ShellPolicy};
+
+/// Rough number of bytes per token for English text and code
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// Limits on what templates may read from the environment of `lakonik`
+#[derive(Clone, Debug)]
+pub struct TemplatePolicy {
+    /// Environment variables that `env(name)` may read; any other name is an error
+    pub env: Vec<String>,
+    /// Bytes that `read_file(path)` may read in total from one environment
+    pub max_read_bytes: u64,
+    /// Rules for the commands that functions such as `git_diff()` run. Files are read relative
+    /// to its working directory, too.
+    pub shell: ShellPolicy,
+    /// Contents that `read_file(path)` returns in place of the files on disk, keyed by their
+    /// path. Fixtures set it so that their prompts don't depend on the files around them.
+    pub files: Option<BTreeMap<String, String>>,
+}
+
+impl Default for TemplatePolicy {
+    fn default() -> Self {
+        Self {
+            env: Vec::new(),
+            max_read_bytes: DEFAULT_MAX_ATTACHMENT_BYTES,
+            shell: ShellPolicy::default(),
+            files: None,
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidOperation, message)
+}
+
+/// `read_file(path)`: the contents of a file, relative to the working directory. Files outside
+/// of it can't be read, and neither can more than what is left of `budget`.
+fn read_file(path: &str, working_dir: Option<&Path>, budget: &AtomicU64) -> Result<String, Error> {
+    let io_error = |err: std::io::Error| invalid(format!("could not read `{path}`: {err}"));
+    if Path::new(path).is_absolute() {
+        return Err(invalid(format!(
+            "`{path}` has to be relative to the working directory"
+        )));
+    }
+
+    let working_dir = match working_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::current_dir().map_err(io_error)?,
+    };
+    let working_dir = working_dir.canonicalize().map_err(io_error)?;
+    // Resolving links and `..` first keeps paths such as `../x` or links out of the directory
+    let file = working_dir.join(path).canonicalize().map_err(io_error)?;
+    if !file.starts_with(&working_dir) {
+        return Err(invalid(format!(
+            "`{path}` is outside of the working directory"
+        )));
+    }
+
+    let size = std::fs::metadata(&file).map_err(io_error)?.len();
+    budget
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(size))
+        .map_err(|left| {
+            invalid(format!(
+                "reading `{path}` ({size} bytes) exceeds the attachment budget, {left} bytes are left"
+            ))
+        })?;
+
+    std::fs::read(&file)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .map_err(io_error)
+}
+
+/// `text | truncate_tokens(n)`: cuts the text down to roughly `n` tokens
+fn truncate_tokens(value: &str, tokens: usize) -> String {
+    let limit = tokens.saturating_mul(BYTES_PER_TOKEN);
+    if value.len() <= limit {
+        return value.to_string();
+    }
+
+    let end = (0..=limit)
+        .rev()
+        .find(|&i| value.is_char_boundary(i))
+        .unwrap_or_default();
+
+    format!("{}...", &value[..end])
+}
+
+/// Language of a code fence for a file extension, as understood by most Markdown renderers
+fn language_for_extension(extension: &str) -> &str {
+    match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "h" => "c",
+        "cc" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "kt" | "kts" => "kotlin",
+        "yml" => "yaml",
+        "md" => "markdown",
+        other => other,
+    }
+}
+
+/// `text | code_fence(lang)`: wraps the text in a Markdown code block. A `lang` that looks like
+/// a path, such as `file.path`, is replaced by the language of its extension.
+fn code_fence(value: &str, lang: Option<&str>) -> String {
+    let lang = match lang {
+        Some(lang) if lang.contains(['.', '/']) => Path::new(lang)
+            .extension()
+            .map(|ext| language_for_extension(&ext.to_string_lossy()).to_string())
+            .unwrap_or_default(),
+        Some(lang) => lang.to_string(),
+        None => String::new(),
+    };
+    // The fence has to be longer than any run of backticks inside the block
+    let longest_run = value
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let newline = if value.ends_with('\n') { "" } else { "\n" };
+
+    format!("{fence}{lang}\n{value}{newline}{fence}")
+}
+
+/// Runs shell code from a template function, which can't await. The code runs on a runtime of
+/// its own, on a separate thread since the caller may already be inside one.
+fn run_shell(code: &str, policy: &ShellPolicy) -> Result<ShellOutput, ShellError> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|source| ShellError::Io {
+                        code: code.to_string(),
+                        source,
+                    })?
+                    .block_on(shell::run(None, code, policy))
+            })
+            .join()
+            .expect("running shell code does not panic")
+    })
+}
+
+/// `git_diff()` or `git_diff(staged=true)`: the changes in the working directory, run under the
+/// same shell policy as the code in a sentence
+fn git_diff(policy: &ShellPolicy, kwargs: Kwargs) -> Result<String, Error> {
+    let staged = kwargs.get::<Option<bool>>("staged")?.unwrap_or_default();
+    kwargs.assert_all_used()?;
+
+    let code = if staged {
+        "git diff --staged"
+    } else {
+        "git diff"
+    };
+    let output = run_shell(code, policy).map_err(|err| invalid(err.to_string()))?;
+    if !output.success() {
+        return Err(invalid(format!(
+            "`{code}` failed: {}",
+            output.stderr.trim()
+        )));
+    }
+
+    Ok(output.stdout)
+}
+
+/// `include_part(name, key=value, ...)`: renders `parts/<name>` with the keyword arguments as
+/// its context
+fn include_part(state: &State, name: &str, kwargs: Kwargs) -> Result<String, Error> {
+    let context = kwargs
+        .args()
+        .map(|key| Ok((key.to_string(), kwargs.get::<Value>(key)?)))
+        .collect::<Result<BTreeMap<_, _>, Error>>()?;
+
+    state
+        .env()
+        .get_template(&format!("parts/{name}"))?
+        .render(context)
+}
+
+/// Registers the filters and functions that Lakonik adds to minijinja. Built-in filters such as
+/// `indent` keep working as documented by minijinja.
+pub fn register(env: &mut Environment, policy: &TemplatePolicy) {
+    let allowed_env = policy.env.clone();
+    let shell = Arc::new(policy.shell.clone());
+    let working_dir = policy.shell.working_dir.clone();
+    let budget = Arc::new(AtomicU64::new(policy.max_read_bytes));
+
+    let files = policy.files.clone();
+
+    env.add_function("read_file", move |path: &str| match &files {
+        Some(files) => files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| invalid(format!("`{path}` has no contents in the fixture"))),
+        None => read_file(path, working_dir.as_deref(), &budget),
+    });
+    env.add_function("git_diff", move |kwargs: Kwargs| git_diff(&shell, kwargs));
+    env.add_function("include_part", include_part);
+    env.add_function("env", move |name: &str| {
+        if !allowed_env.iter().any(|allowed| allowed == name) {
+            return Err(invalid(format!(
+                "environment variable `{name}` is not allowed, pass `--template-env {name}`"
+            )));
+        }
+
+        Ok(std::env::var(name).ok())
+    });
+    env.add_filter("truncate_tokens", truncate_tokens);
+    env.add_filter("code_fence", code_fence);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+    use rstest::rstest;
+
+    fn render(source: &str, policy: &TemplatePolicy) -> Result<String, Error> {
+        let mut env = Environment::new();
+        register(&mut env, policy);
+        env.add_template("parts/greeting", "hello {{ name }}")
+            .unwrap();
+
+        env.render_str(source, context! {})
+    }
+
+    #[rstest]
+    #[case("{{ 'abcdefghij' | truncate_tokens(2) }}", "abcdefgh...")]
+    #[case("{{ 'abc' | truncate_tokens(2) }}", "abc")]
+    #[case(
+        "{{ 'fn main() {}' | code_fence('src/main.rs') }}",
+        "```rust\nfn main() {}\n```"
+    )]
+    #[case("{{ 'a ``` b' | code_fence('text') }}", "````text\na ``` b\n````")]
+    #[case("{{ 'x' | code_fence }}", "```\nx\n```")]
+    #[case("{{ 'a\nb' | indent(2, true) }}", "  a\n  b")]
+    #[case("{{ include_part('greeting', name='world') }}", "hello world")]
+    fn filters_and_functions(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(
+            render(source, &TemplatePolicy::default()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn read_file_reads_relative_to_working_directory() {
+        let rendered = render("{{ read_file('Cargo.toml') }}", &TemplatePolicy::default());
+
+        assert_eq!(
+            rendered.unwrap(),
+            std::fs::read_to_string("Cargo.toml").unwrap()
+        );
+        assert!(
+            render(
+                "{{ read_file('does/not/exist') }}",
+                &TemplatePolicy::default()
+            )
+            .is_err()
+        );
+    }
+
+    #[rstest]
+    #[case("/etc/hostname")]
+    #[case("../secret")]
+    #[case("inner/../../secret")]
+    #[case("link")]
+    fn read_file_stays_in_the_working_directory(#[case] path: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let working_dir = dir.path().join("work");
+        std::fs::create_dir_all(working_dir.join("inner")).unwrap();
+        std::fs::write(dir.path().join("secret"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("secret"), working_dir.join("link")).unwrap();
+        let policy = TemplatePolicy {
+            shell: ShellPolicy {
+                working_dir: Some(working_dir),
+                ..ShellPolicy::default()
+            },
+            ..TemplatePolicy::default()
+        };
+
+        let err = render(&format!("{{{{ read_file('{path}') }}}}"), &policy).unwrap_err();
+        assert!(!err.to_string().contains("secret\n"), "{err}");
+    }
+
+    #[test]
+    fn read_file_counts_against_the_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), "12345").unwrap();
+        let policy = TemplatePolicy {
+            max_read_bytes: 8,
+            shell: ShellPolicy {
+                working_dir: Some(dir.path().to_path_buf()),
+                ..ShellPolicy::default()
+            },
+            ..TemplatePolicy::default()
+        };
+
+        assert_eq!(render("{{ read_file('a') }}", &policy).unwrap(), "12345");
+        let err = render("{{ read_file('a') }}{{ read_file('a') }}", &policy).unwrap_err();
+        assert!(err.to_string().contains("attachment budget"), "{err}");
+    }
+
+    #[test]
+    fn git_diff_follows_the_shell_policy() {
+        let denied = TemplatePolicy {
+            shell: ShellPolicy {
+                deny: vec!["git".to_string()],
+                ..ShellPolicy::default()
+            },
+            ..TemplatePolicy::default()
+        };
+        let err = render("{{ git_diff() }}", &denied).unwrap_err();
+        assert!(err.to_string().contains("denied"), "{err}");
+
+        let stubbed = TemplatePolicy {
+            shell: ShellPolicy {
+                stubs: Some([("git diff --staged".to_string(), "staged".to_string())].into()),
+                ..ShellPolicy::default()
+            },
+            ..TemplatePolicy::default()
+        };
+        assert_eq!(
+            render("{{ git_diff(staged=true) }}", &stubbed).unwrap(),
+            "staged"
+        );
+    }
+
+    #[tokio::test]
+    async fn git_diff_runs_inside_a_runtime() {
+        let policy = TemplatePolicy {
+            shell: ShellPolicy {
+                stubs: Some([("git diff".to_string(), "diff".to_string())].into()),
+                ..ShellPolicy::default()
+            },
+            ..TemplatePolicy::default()
+        };
+
+        assert_eq!(render("{{ git_diff() }}", &policy).unwrap(), "diff");
+    }
+
+    #[test]
+    fn env_is_limited_by_policy() {
+        let policy = TemplatePolicy {
+            env: vec!["PATH".to_string()],
+            ..TemplatePolicy::default()
+        };
+
+        assert_eq!(
+            render("{{ env('PATH') }}", &policy).unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+        assert!(render("{{ env('HOME') }}", &policy).is_err());
+    }
+}
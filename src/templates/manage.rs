@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use minijinja::Environment;
+use walkdir::WalkDir;
+
+use super::fixtures::{FIXTURE_SUFFIX, Fixture, is_fixture};
+use super::{
+    PROJECT_TEMPLATE_DIR, Template, TemplateSource, TemplateType, get_all_templates,
+    project_template_dir, registry, templates_from_dir, user_template_location, which_template,
+};
+use crate::ast::is_valid_name;
+use crate::config::project_root;
+use crate::error::LakonikError;
+
+pub(super) fn io_error(path: &Path) -> impl Fn(std::io::Error) -> LakonikError + '_ {
+    move |source| LakonikError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+pub(super) fn config_error(message: impl Into<String>) -> LakonikError {
+    LakonikError::Config {
+        message: message.into(),
+    }
+}
+
+/// Directory that management commands write to for a layer, even if it does not exist yet
+pub fn layer_dir(source: TemplateSource) -> Result<PathBuf, LakonikError> {
+    match source {
+        TemplateSource::BuiltIn => Err(config_error("built-in templates are read-only")),
+        TemplateSource::User => user_template_location()
+            .ok_or_else(|| config_error("could not determine the user template directory")),
+        TemplateSource::Project => project_template_dir()
+            .or_else(|| Some(project_root()?.join(PROJECT_TEMPLATE_DIR)))
+            .ok_or_else(|| config_error("could not determine the project template directory")),
+    }
+}
+
+/// A bare name refers to a verb, anything with a `/` is a path into one of the template
+/// directories, such as `parts/shell`. Paths can't leave the layer they are used in.
+fn template_path(name: &str) -> Result<String, LakonikError> {
+    if !name.contains('/') {
+        if !is_valid_name(name) {
+            return Err(config_error(format!("`{name}` is not a valid verb name")));
+        }
+        return Ok(format!("verbs/{name}"));
+    }
+
+    let invalid = |reason: &str| config_error(format!("`{name}` is not a template path: {reason}"));
+    if name.starts_with('/') || name.contains('\\') || Path::new(name).is_absolute() {
+        return Err(invalid("it has to be relative to the template directory"));
+    }
+    if name
+        .split('/')
+        .any(|component| matches!(component, "" | "." | ".."))
+    {
+        return Err(invalid("it has an empty, `.` or `..` component"));
+    }
+    if TemplateType::from_path(name) == TemplateType::Other {
+        return Err(invalid(
+            "it has to start with `verbs/`, `parts/`, `vocatives/`, `system/` or `layouts/`",
+        ));
+    }
+
+    Ok(name.to_string())
+}
+
+/// The template that is in use for every path, sorted by path
+pub fn list_templates() -> Vec<Template> {
+    get_all_templates()
+        .map(|t| (t.path.clone(), t))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+/// The template a name resolves to, as in `templates which`
+pub fn resolve_template(name: &str) -> Result<Template, LakonikError> {
+    which_template(name)
+        .into_iter()
+        .next()
+        .ok_or_else(|| LakonikError::TemplateNotFound {
+            name: name.to_string(),
+            span: None,
+        })
+}
+
+fn scaffold_in(dir: &Path, path: &str, base: &str) -> Result<PathBuf, LakonikError> {
+    let file_path = dir.join(path);
+    if file_path.exists() {
+        return Err(config_error(format!("template `{path}` already exists")));
+    }
+
+    let contents = format!(
+        "+++\ndescription = \"\"\n+++\n\
+         {{% extends \"{base}\" %}}{{% block body %}}{{{{ description }}}}{{% endblock %}}\n"
+    );
+    write_template(&file_path, &contents)?;
+
+    Ok(file_path)
+}
+
+fn write_template(file_path: &Path, contents: &str) -> Result<(), LakonikError> {
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    fs::write(file_path, contents).map_err(io_error(file_path))?;
+    registry::invalidate();
+
+    Ok(())
+}
+
+/// Creates a new template in a layer that extends `base`, returning the file it was written to
+pub fn scaffold_template(
+    name: &str,
+    base: &str,
+    source: TemplateSource,
+) -> Result<PathBuf, LakonikError> {
+    resolve_template(base)?;
+
+    scaffold_in(&layer_dir(source)?, &template_path(name)?, base)
+}
+
+/// The file to edit for a template. Templates from other layers are copied into `source`
+/// first, so that editing overrides them instead of changing them.
+pub fn editable_template(name: &str, source: TemplateSource) -> Result<PathBuf, LakonikError> {
+    template_path(name)?;
+    let template = resolve_template(name)?;
+    if template.source == source
+        && let Some(file_path) = template.file_path()
+    {
+        return Ok(file_path);
+    }
+
+    let file_path = layer_dir(source)?.join(&template.path);
+    write_template(&file_path, &template.contents)?;
+
+    Ok(file_path)
+}
+
+/// Removes a template from a layer, returning the file that was deleted
+pub fn delete_template(name: &str, source: TemplateSource) -> Result<PathBuf, LakonikError> {
+    template_path(name)?;
+    let file_path = which_template(name)
+        .into_iter()
+        .find(|t| t.source == source)
+        .and_then(|t| t.file_path())
+        .ok_or_else(|| {
+            config_error(format!(
+                "template `{name}` does not exist in the {source} layer"
+            ))
+        })?;
+
+    fs::remove_file(&file_path).map_err(io_error(&file_path))?;
+    registry::invalidate();
+
+    Ok(file_path)
+}
+
+fn is_tarball(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// The fixture next to a template, as the path and contents it has in a pack
+fn fixture_of(dir: &Path, template: &Template) -> Option<(String, String)> {
+    let path = format!("{}{FIXTURE_SUFFIX}", template.path);
+    let contents = fs::read_to_string(dir.join(&path)).ok()?;
+
+    Some((path, contents))
+}
+
+fn export_from(
+    dir: &Path,
+    source: TemplateSource,
+    paths: &[String],
+    dest: &Path,
+) -> Result<usize, LakonikError> {
+    let templates = templates_from_dir(dir, source)
+        .filter(|t| is_pack_template(&t.path))
+        .filter(|t| paths.is_empty() || paths.contains(&t.path))
+        .collect::<Vec<_>>();
+
+    if let Some(missing) = paths
+        .iter()
+        .find(|p| !templates.iter().any(|t| &t.path == *p))
+    {
+        return Err(config_error(format!(
+            "template `{missing}` does not exist in the {source} layer"
+        )));
+    }
+
+    // Fixtures travel with their templates, so that a pack can be tested where it is imported
+    let files = templates
+        .iter()
+        .flat_map(|t| {
+            [
+                Some((t.path.clone(), t.contents.clone())),
+                fixture_of(dir, t),
+            ]
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
+    if !is_tarball(dest) {
+        for (path, contents) in &files {
+            write_template(&dest.join(path), contents)?;
+        }
+
+        return Ok(templates.len());
+    }
+
+    let file = fs::File::create(dest).map_err(io_error(dest))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (path, contents) in &files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, path, contents.as_bytes())
+            .map_err(io_error(dest))?;
+    }
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(io_error(dest))?;
+
+    Ok(templates.len())
+}
+
+/// Writes the templates of a layer, or only the named ones, to a directory or a `.tar.gz`
+pub fn export_templates(
+    source: TemplateSource,
+    names: &[String],
+    dest: &Path,
+) -> Result<usize, LakonikError> {
+    let paths = names
+        .iter()
+        .map(|name| template_path(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    export_from(&layer_dir(source)?, source, &paths, dest)
+}
+
+/// Catches templates that would break every prompt once they are installed
+fn validate(template: &Template) -> Result<(), LakonikError> {
+    if let Some(err) = &template.metadata_error {
+        return Err(config_error(format!(
+            "invalid front-matter in template `{}`: {err}",
+            template.path
+        )));
+    }
+
+    Environment::new()
+        .template_from_named_str(&template.path, template.body())
+        .map(|_| ())
+        .map_err(|source| LakonikError::Render {
+            template: template.path.clone(),
+            source,
+            span: None,
+        })
+}
+
+/// The files of a tarball by their path. Entries that could land outside of the layer are
+/// refused rather than skipped, since no honest pack has them.
+fn read_tarball(src: &Path) -> Result<Vec<(String, String)>, LakonikError> {
+    let file = fs::File::open(src).map_err(io_error(src))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut files = Vec::new();
+    for entry in archive.entries().map_err(io_error(src))? {
+        let mut entry = entry.map_err(io_error(src))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(io_error(src))?
+            .to_string_lossy()
+            .into_owned();
+        let path = path.trim_start_matches("./").to_string();
+        if path.split(['/', '\\']).any(|component| component == "..")
+            || Path::new(&path).is_absolute()
+        {
+            return Err(config_error(format!(
+                "`{}` has an entry outside of the template directory: `{path}`",
+                src.display()
+            )));
+        }
+        let mut contents = String::new();
+        if entry.read_to_string(&mut contents).is_ok() {
+            files.push((path, contents));
+        }
+    }
+
+    Ok(files)
+}
+
+/// The files of a directory by their path
+fn read_pack_dir(src: &Path) -> Vec<(String, String)> {
+    WalkDir::new(src)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|entry| {
+            let rel = entry.path().strip_prefix(src).ok()?;
+            let contents = fs::read_to_string(entry.path()).ok()?;
+
+            Some((rel.to_string_lossy().replace('\\', "/"), contents))
+        })
+        .collect()
+}
+
+/// Whether a file of a pack is a template or the fixture of one. Anything else, such as a
+/// README or a `.git` directory, is left out of the import.
+fn is_pack_template(path: &str) -> bool {
+    let template = path.strip_suffix(FIXTURE_SUFFIX).unwrap_or(path);
+
+    template.contains('/') && template_path(template).is_ok()
+}
+
+fn import_into(
+    dir: &Path,
+    source: TemplateSource,
+    src: &Path,
+    force: bool,
+) -> Result<Vec<String>, LakonikError> {
+    let files = if is_tarball(src) {
+        read_tarball(src)?
+    } else if src.is_dir() {
+        read_pack_dir(src)
+    } else {
+        return Err(config_error(format!(
+            "`{}` is neither a directory nor a .tar.gz file",
+            src.display()
+        )));
+    };
+
+    let mut files = files
+        .into_iter()
+        .filter(|(path, _)| is_pack_template(path))
+        .collect::<Vec<_>>();
+    files.sort();
+    for (path, contents) in &files {
+        if is_fixture(path) {
+            toml::from_str::<Fixture>(contents)
+                .map_err(|err| config_error(format!("invalid fixture `{path}`: {err}")))?;
+        } else {
+            validate(&Template::new(path.clone(), contents.clone(), source))?;
+        }
+    }
+
+    let existing = files
+        .iter()
+        .filter(|(path, _)| dir.join(path).exists())
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>();
+    if !force && !existing.is_empty() {
+        return Err(config_error(format!(
+            "these templates already exist, use --force to overwrite them: {}",
+            existing.join(", ")
+        )));
+    }
+
+    for (path, contents) in &files {
+        write_template(&dir.join(path), contents)?;
+    }
+
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Installs the templates of a directory or `.tar.gz` into a layer, returning their paths
+pub fn import_templates(
+    src: &Path,
+    source: TemplateSource,
+    force: bool,
+) -> Result<Vec<String>, LakonikError> {
+    import_into(&layer_dir(source)?, source, src, force)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn layer_with(templates: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in templates {
+            write_template(&dir.path().join(path), contents).unwrap();
+        }
+
+        dir
+    }
+
+    #[rstest]
+    #[case("review", Some("verbs/review"))]
+    #[case("parts/shell", Some("parts/shell"))]
+    #[case("verbs/nested/review", Some("verbs/nested/review"))]
+    #[case("../../x", None)]
+    #[case("verbs/../../x", None)]
+    #[case("verbs/./x", None)]
+    #[case("verbs//x", None)]
+    #[case("verbs/", None)]
+    #[case("/etc/passwd", None)]
+    #[case("verbs\\..\\x/y", None)]
+    #[case("elsewhere/x", None)]
+    #[case("Not Valid", None)]
+    fn template_paths_stay_in_the_layer(#[case] name: &str, #[case] expected: Option<&str>) {
+        assert_eq!(template_path(name).ok().as_deref(), expected);
+    }
+
+    #[test]
+    fn traversing_names_are_rejected_before_touching_files() {
+        let layer = tempfile::tempdir().unwrap();
+        let outside = layer.path().join("outside");
+
+        assert!(delete_template("../outside", TemplateSource::User).is_err());
+        assert!(
+            export_templates(TemplateSource::User, &["../outside".to_string()], &outside).is_err()
+        );
+        assert!(!outside.exists());
+    }
+
+    #[rstest]
+    #[case("pack")]
+    #[case("pack.tar.gz")]
+    fn export_and_import_round_trip(#[case] pack: &str) {
+        let from = layer_with(&[
+            ("verbs/review", "review {{ description }}"),
+            ("parts/x", "x"),
+        ]);
+        let to = tempfile::tempdir().unwrap();
+        let pack = to.path().join(pack);
+
+        let exported = export_from(
+            from.path(),
+            TemplateSource::User,
+            &["verbs/review".to_string()],
+            &pack,
+        )
+        .unwrap();
+        let layer = to.path().join("layer");
+        let imported = import_into(&layer, TemplateSource::User, &pack, false).unwrap();
+
+        assert_eq!(exported, 1);
+        assert_eq!(imported, vec!["verbs/review"]);
+        assert_eq!(
+            fs::read_to_string(layer.join("verbs/review")).unwrap(),
+            "review {{ description }}"
+        );
+    }
+
+    /// A pack with entries as they are, even ones that `tar::Builder` would refuse
+    fn tarball_with(dir: &Path, entries: &[(&str, &str)]) -> PathBuf {
+        let path = dir.join("pack.tar.gz");
+        let file = fs::File::create(&path).unwrap();
+        let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append(&header, contents.as_bytes()).unwrap();
+        }
+        archive.into_inner().unwrap().finish().unwrap();
+
+        path
+    }
+
+    #[rstest]
+    #[case("pack")]
+    #[case("pack.tar.gz")]
+    fn fixtures_travel_with_their_templates(#[case] pack: &str) {
+        let from = layer_with(&[
+            ("verbs/review", "review {{ description }}"),
+            (
+                "verbs/review.test.toml",
+                "[[case]]\ninput = \"qwen3 review x\"\n",
+            ),
+        ]);
+        let to = tempfile::tempdir().unwrap();
+        let pack = to.path().join(pack);
+
+        export_from(from.path(), TemplateSource::User, &[], &pack).unwrap();
+        let layer = to.path().join("layer");
+        let imported = import_into(&layer, TemplateSource::User, &pack, false).unwrap();
+
+        assert_eq!(imported, vec!["verbs/review", "verbs/review.test.toml"]);
+        assert!(layer.join("verbs/review.test.toml").exists());
+    }
+
+    #[test]
+    fn import_leaves_out_files_that_are_not_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack = tarball_with(
+            dir.path(),
+            &[
+                ("README.md", "# Templates"),
+                (".git/config", "[core]"),
+                ("verbs/review", "review"),
+            ],
+        );
+        let layer = dir.path().join("layer");
+
+        let imported = import_into(&layer, TemplateSource::User, &pack, false).unwrap();
+
+        assert_eq!(imported, vec!["verbs/review"]);
+        assert!(!layer.join("README.md").exists());
+        assert!(!layer.join(".git").exists());
+    }
+
+    #[test]
+    fn import_refuses_tarballs_with_entries_outside_the_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack = tarball_with(
+            dir.path(),
+            &[("verbs/review", "review"), ("../escaped", "escaped")],
+        );
+        let layer = dir.path().join("layer");
+
+        assert!(import_into(&layer, TemplateSource::User, &pack, false).is_err());
+        assert!(!dir.path().parent().unwrap().join("escaped").exists());
+        assert!(!layer.join("verbs/review").exists());
+    }
+
+    #[test]
+    fn import_refuses_to_overwrite_without_force() {
+        let pack = layer_with(&[("verbs/review", "new")]);
+        let layer = layer_with(&[("verbs/review", "old")]);
+
+        assert!(import_into(layer.path(), TemplateSource::User, pack.path(), false).is_err());
+        assert_eq!(
+            fs::read_to_string(layer.path().join("verbs/review")).unwrap(),
+            "old"
+        );
+
+        import_into(layer.path(), TemplateSource::User, pack.path(), true).unwrap();
+        assert_eq!(
+            fs::read_to_string(layer.path().join("verbs/review")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn import_rejects_broken_templates() {
+        let pack = layer_with(&[("verbs/broken", "{% if %}")]);
+        let layer = tempfile::tempdir().unwrap();
+
+        assert!(import_into(layer.path(), TemplateSource::User, pack.path(), false).is_err());
+        assert!(!layer.path().join("verbs/broken").exists());
+    }
+
+    #[test]
+    fn scaffold_extends_base() {
+        let layer = tempfile::tempdir().unwrap();
+
+        let file_path = scaffold_in(layer.path(), "verbs/review", "verbs/base/base").unwrap();
+        let template = Template::new(
+            "verbs/review".to_string(),
+            fs::read_to_string(&file_path).unwrap(),
+            TemplateSource::User,
+        );
+
+        assert!(template.metadata_error.is_none());
+        assert!(
+            template
+                .body()
+                .starts_with("{% extends \"verbs/base/base\" %}")
+        );
+        assert!(scaffold_in(layer.path(), "verbs/review", "verbs/base/base").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::Part;
+
+/// Delimits TOML front-matter at the very start of a template
+const FRONT_MATTER_DELIMITER: &str = "+++";
+
+/// Kinds of parts a sentence can contain, as named in front-matter
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartKind {
+    Freeform,
+    File,
+    Shell,
+    Stdin,
+}
+
+impl PartKind {
+    pub fn of(part: &Part) -> Self {
+        match part {
+            Part::Freeform(_) => PartKind::Freeform,
+            Part::FilePath(_) => PartKind::File,
+            Part::InlineShell(_) => PartKind::Shell,
+            Part::Stdin(_) => PartKind::Stdin,
+        }
+    }
+}
+
+impl fmt::Display for PartKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PartKind::Freeform => "free-form text",
+            PartKind::File => "a file (`@path`)",
+            PartKind::Shell => "inline code (`$(...)`)",
+            PartKind::Stdin => "piped input (`-`)",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// What the model is expected to answer with, passed on to whatever runs the prompt
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Markdown,
+    Json,
+    Diff,
+}
+
+/// Optional settings at the top of a template, between `+++` lines:
+///
+/// ```text
+/// +++
+/// description = "Review a change"
+/// aliases = ["rv"]
+/// required-parts = ["file"]
+/// +++
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TemplateMetadata {
+    pub description: Option<String>,
+    /// Other names the verb or vocative can be invoked with
+    pub aliases: Vec<String>,
+    /// Vocative to use when a sentence does not name one
+    pub default_vocative: Option<String>,
+    /// Each of these has to appear at least once in the sentence
+    pub required_parts: Vec<PartKind>,
+    /// None of these may appear in the sentence
+    pub forbidden_parts: Vec<PartKind>,
+    /// Modifiers the template knows how to handle
+    pub modifiers: Vec<String>,
+    pub output: OutputFormat,
+    /// Only meant to be extended by other templates, never invoked directly
+    #[serde(rename = "abstract")]
+    pub is_abstract: bool,
+}
+
+impl TemplateMetadata {
+    /// Checks the parts of a sentence against the required and forbidden kinds, returning a
+    /// message for the first violation
+    pub fn check_parts(&self, parts: &[Part]) -> Result<(), String> {
+        let kinds = parts.iter().map(PartKind::of).collect::<Vec<_>>();
+
+        if let Some(missing) = self.required_parts.iter().find(|k| !kinds.contains(k)) {
+            return Err(format!("this verb requires {missing}"));
+        }
+        if let Some(forbidden) = self.forbidden_parts.iter().find(|k| kinds.contains(k)) {
+            return Err(format!("this verb does not accept {forbidden}"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a template into its raw front-matter, if any, and the minijinja body
+pub fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let Some(rest) = contents
+        .strip_prefix(FRONT_MATTER_DELIMITER)
+        .and_then(|rest| rest.strip_prefix('\n'))
+    else {
+        return (None, contents);
+    };
+
+    let closing = format!("\n{FRONT_MATTER_DELIMITER}");
+    let (front_matter, body) = match rest.strip_prefix(FRONT_MATTER_DELIMITER) {
+        Some(body) => ("", body),
+        None => match rest.find(&closing) {
+            Some(end) => (&rest[..end], &rest[end + closing.len()..]),
+            None => return (None, contents),
+        },
+    };
+
+    (Some(front_matter), body.strip_prefix('\n').unwrap_or(body))
+}
+
+pub fn parse_metadata(front_matter: &str) -> Result<TemplateMetadata, String> {
+    toml::from_str(front_matter).map_err(|err| err.message().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Span, parse_statement};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "+++\ndescription = \"x\"\n+++\nbody",
+        Some("description = \"x\""),
+        "body"
+    )]
+    #[case("+++\n+++\nbody", Some(""), "body")]
+    #[case("body", None, "body")]
+    #[case("+++\nunterminated", None, "+++\nunterminated")]
+    fn front_matter_is_split_from_body(
+        #[case] contents: &str,
+        #[case] front_matter: Option<&str>,
+        #[case] body: &str,
+    ) {
+        assert_eq!(split_front_matter(contents), (front_matter, body));
+    }
+
+    #[test]
+    fn metadata_is_parsed() {
+        let metadata = parse_metadata(
+            "description = \"Review a change\"\naliases = [\"rv\"]\n\
+             required-parts = [\"file\"]\noutput = \"diff\"",
+        )
+        .unwrap();
+
+        assert_eq!(metadata.description.as_deref(), Some("Review a change"));
+        assert_eq!(metadata.aliases, vec!["rv"]);
+        assert_eq!(metadata.required_parts, vec![PartKind::File]);
+        assert_eq!(metadata.output, OutputFormat::Diff);
+        assert!(parse_metadata("descripton = \"typo\"").is_err());
+    }
+
+    #[rstest]
+    #[case("qwen3 review @foo.rs", Ok(()))]
+    #[case("qwen3 review foo", Err("this verb requires a file (`@path`)"))]
+    #[case(
+        "qwen3 review @foo.rs $(ls)",
+        Err("this verb does not accept inline code (`$(...)`)")
+    )]
+    fn parts_are_checked(#[case] input: &str, #[case] expected: Result<(), &str>) {
+        let metadata = TemplateMetadata {
+            required_parts: vec![PartKind::File],
+            forbidden_parts: vec![PartKind::Shell],
+            ..TemplateMetadata::default()
+        };
+        let (_, sentence) = parse_statement(Span::new(input)).unwrap();
+
+        assert_eq!(
+            metadata.check_parts(&sentence.parts),
+            expected.map_err(str::to_string)
+        );
+    }
+}
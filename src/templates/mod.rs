@@ -1,5 +1,12 @@
 #![allow(dead_code)]
 
+pub mod check;
+pub mod fixtures;
+pub mod functions;
+pub mod manage;
+pub mod metadata;
+mod registry;
+
 use directories::ProjectDirs;
 use include_dir::{Dir, include_dir};
 use minijinja::Environment;
@@ -9,27 +16,168 @@ use std::{
 };
 use walkdir::WalkDir;
 
+use crate::config::project_root;
+use crate::error::LakonikError;
+use functions::TemplatePolicy;
+use metadata::{TemplateMetadata, parse_metadata, split_front_matter};
+
 /// Embedded templates compiled into the binary
 static BUILT_IN_TEMPLATES_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/src/templates/built_in");
 
-/// Where a template comes from
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Where a template comes from, from lowest to highest precedence
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum TemplateSource {
     BuiltIn,
+    /// The user's configuration directory, or `LAKONIK_CONFIG`
     User,
+    /// `.lakonik/templates` in the project root, see [`crate::config::project_root`], or one of its
+    /// parents
+    Project,
 }
 
+impl std::fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TemplateSource::BuiltIn => "built-in",
+            TemplateSource::User => "user",
+            TemplateSource::Project => "project",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// What a template is used for, given by the directory it lives in
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TemplateType {
+    /// `verbs/`: turns a sentence into a prompt
     Verb,
+    /// `parts/`: renders a part of a sentence, such as the output of inline code
+    Part,
+    /// `vocatives/`: system prompt for the model a sentence is addressed to
+    Vocative,
+    /// `system/`: system prompts shared between vocatives
+    System,
+    /// `layouts/`: building blocks that other templates extend or include
+    Layout,
+    /// Anything outside of the known directories, only usable through `extends` or `include`
+    Other,
+}
+
+impl TemplateType {
+    pub fn from_path(path: &str) -> Self {
+        match path.split_once('/').map(|(dir, _)| dir) {
+            Some("verbs") => TemplateType::Verb,
+            Some("parts") => TemplateType::Part,
+            Some("vocatives") => TemplateType::Vocative,
+            Some("system") => TemplateType::System,
+            Some("layouts") => TemplateType::Layout,
+            _ => TemplateType::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for TemplateType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TemplateType::Verb => "verb",
+            TemplateType::Part => "part",
+            TemplateType::Vocative => "vocative",
+            TemplateType::System => "system",
+            TemplateType::Layout => "layout",
+            TemplateType::Other => "other",
+        };
+
+        write!(f, "{name}")
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Template {
     pub path: String,
+    /// The whole file, including front-matter
     pub contents: String,
     pub source: TemplateSource,
     pub template_type: TemplateType,
+    pub metadata: TemplateMetadata,
+    /// Why the front-matter could not be parsed, in which case `metadata` is the default
+    pub metadata_error: Option<String>,
+}
+
+impl Template {
+    pub fn new(path: String, contents: String, source: TemplateSource) -> Self {
+        let (metadata, metadata_error) = match split_front_matter(&contents).0.map(parse_metadata) {
+            Some(Ok(metadata)) => (metadata, None),
+            Some(Err(err)) => (TemplateMetadata::default(), Some(err)),
+            None => (TemplateMetadata::default(), None),
+        };
+
+        Template {
+            template_type: TemplateType::from_path(&path),
+            path,
+            contents,
+            source,
+            metadata,
+            metadata_error,
+        }
+    }
+
+    /// The minijinja source, without front-matter
+    pub fn body(&self) -> &str {
+        split_front_matter(&self.contents).1
+    }
+
+    /// Name of a verb template as typed in a sentence, unless the verb can't be invoked
+    pub fn verb_name(&self) -> Option<&str> {
+        if self.template_type != TemplateType::Verb || self.metadata.is_abstract {
+            return None;
+        }
+
+        self.path.strip_prefix("verbs/")
+    }
+
+    /// Location of the template on disk, if it has one
+    pub fn file_path(&self) -> Option<PathBuf> {
+        self.root_dir().map(|dir| dir.join(&self.path))
+    }
+
+    /// Directory of the layer the template was loaded from
+    pub fn root_dir(&self) -> Option<PathBuf> {
+        match self.source {
+            TemplateSource::BuiltIn => None,
+            TemplateSource::User => user_template_dir(),
+            TemplateSource::Project => project_template_dir(),
+        }
+    }
+
+    /// Human readable description from the front-matter, or else from the first `{# ... #}`
+    /// comment of the template
+    pub fn description(&self) -> Option<String> {
+        if let Some(description) = &self.metadata.description {
+            return Some(description.clone());
+        }
+
+        let (_, rest) = self.body().split_once("{#")?;
+        let (comment, _) = rest.split_once("#}")?;
+        let comment = comment.trim_matches(|c: char| c == '-' || c.is_whitespace());
+
+        (!comment.is_empty()).then(|| comment.to_string())
+    }
+
+    /// Names of the variables the template expects to receive when rendered
+    pub fn variables(&self, environment: &Environment) -> Vec<String> {
+        let mut variables = environment
+            .get_template(&self.path)
+            .map(|t| {
+                t.undeclared_variables(false)
+                    .into_iter()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        variables.sort();
+
+        variables
+    }
 }
 
 pub fn get_built_in_templates() -> impl Iterator<Item = Template> {
@@ -37,22 +185,25 @@ pub fn get_built_in_templates() -> impl Iterator<Item = Template> {
         .find("**/*")
         .expect("Failed to traverse embedded templates")
         .filter_map(|entry| {
-            entry.as_file().map(|f| Template {
-                template_type: TemplateType::Verb,
-                source: TemplateSource::BuiltIn,
-                path: f
-                    .path()
-                    .to_string_lossy()
-                    .into_owned()
-                    .replace("templates/", ""),
-                contents: f
-                    .contents_utf8()
-                    .expect("Invalid UTF-8 in embedded template")
-                    .to_string(),
+            entry.as_file().map(|f| {
+                Template::new(
+                    f.path()
+                        .to_string_lossy()
+                        .into_owned()
+                        .replace("templates/", ""),
+                    f.contents_utf8()
+                        .expect("Invalid UTF-8 in embedded template")
+                        .to_string(),
+                    TemplateSource::BuiltIn,
+                )
             })
         })
 }
 
+pub fn get_built_in_template(template_name: &str) -> Option<Template> {
+    get_built_in_templates().find(|t| t.path == template_name)
+}
+
 fn templates_from_dir<P: AsRef<Path>>(
     base: P,
     source: TemplateSource,
@@ -66,75 +217,407 @@ fn templates_from_dir<P: AsRef<Path>>(
         .filter_map(move |entry| {
             let abs_path = entry.path();
             let rel = abs_path.strip_prefix(&base).ok()?;
+            if fixtures::is_fixture(&rel.to_string_lossy()) {
+                return None;
+            }
             let contents = fs::read_to_string(abs_path).ok()?;
 
-            Some(Template {
-                template_type: TemplateType::Verb,
-                source,
-                path: rel.to_string_lossy().replace('\\', "/"),
+            Some(Template::new(
+                rel.to_string_lossy().replace('\\', "/"),
                 contents,
-            })
+                source,
+            ))
         })
 }
 
-fn user_template_dir() -> Option<PathBuf> {
+/// Where user templates live, whether or not the directory exists yet
+fn user_template_location() -> Option<PathBuf> {
     if let Ok(p) = std::env::var("LAKONIK_CONFIG") {
         return Some(PathBuf::from(p));
     }
 
-    ProjectDirs::from("", "", "lakonik")
-        .map(|pd| pd.config_dir().join("templates"))
-        .filter(|p| p.exists())
+    ProjectDirs::from("", "", "lakonik").map(|pd| pd.config_dir().join("templates"))
 }
 
-pub fn get_user_templates() -> impl Iterator<Item = Template> {
-    user_template_dir()
-        .into_iter()
-        .flat_map(|dir| templates_from_dir(dir, TemplateSource::User))
+pub fn user_template_dir() -> Option<PathBuf> {
+    // An explicit `LAKONIK_CONFIG` is used even before it exists
+    if std::env::var_os("LAKONIK_CONFIG").is_some() {
+        return user_template_location();
+    }
+
+    user_template_location().filter(|p| p.exists())
 }
 
-pub fn create_user_template(template_name: &str, template_source: &str) {
-    if let Some(base_dir) = user_template_dir() {
-        let file_path = base_dir.join(template_name);
+/// Directory of project templates that are checked in next to the code they are used for
+const PROJECT_TEMPLATE_DIR: &str = ".lakonik/templates";
 
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent).expect("Failed to create template sub-directory");
-        }
+pub fn find_project_template_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_TEMPLATE_DIR))
+        .find(|dir| dir.is_dir())
+}
 
-        fs::write(
-            &file_path,
-            format!(
-                "{{% extends \"verbs/base/base\" %}}{{% block body %}}\
-                 {template_source} {{{{description}}}}{{% endblock %}}"
-            ),
-        )
-        .expect("Failed to write user template");
+/// The closest `.lakonik/templates` directory, starting from the project root
+pub fn project_template_dir() -> Option<PathBuf> {
+    find_project_template_dir(&project_root()?)
+}
+
+pub fn get_project_templates() -> impl Iterator<Item = Template> {
+    get_all_templates().filter(|t| t.source == TemplateSource::Project)
+}
+
+pub fn get_user_templates() -> impl Iterator<Item = Template> {
+    get_all_templates().filter(|t| t.source == TemplateSource::User)
+}
+
+/// Contents of the template defined by a verb assignment. A plain value is put in front of the
+/// description, while a value with minijinja syntax is used as the whole body, so that it decides
+/// where `description` goes.
+pub fn assignment_contents(value: &str) -> String {
+    let body = if value.contains("{{") || value.contains("{%") {
+        value.to_string()
     } else {
-        panic!("User template directory does not exist");
+        format!("{value} {{{{description}}}}")
+    };
+
+    format!("{{% extends \"verbs/base/base\" %}}{{% block body %}}{body}{{% endblock %}}")
+}
+
+pub fn create_user_template(template_name: &str, contents: &str) -> Result<(), LakonikError> {
+    let base_dir = user_template_dir().ok_or_else(|| LakonikError::Config {
+        message: "user template directory does not exist; create it or point \
+                  LAKONIK_CONFIG to a directory"
+            .to_string(),
+    })?;
+    let file_path = base_dir.join(template_name);
+    let io_error = |source| LakonikError::Io {
+        path: file_path.clone(),
+        source,
+    };
+
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
     }
+
+    fs::write(&file_path, contents).map_err(io_error)?;
+    registry::invalidate();
+
+    Ok(())
 }
 
-pub fn delete_user_template(template_name: &str) {
+pub fn delete_user_template(template_name: &str) -> Result<(), LakonikError> {
     if let Some(dir) = user_template_dir() {
         let file_path = dir.join(template_name);
         if file_path.exists() {
-            fs::remove_file(file_path).expect("Failed to delete user template");
+            fs::remove_file(&file_path).map_err(|source| LakonikError::Io {
+                path: file_path,
+                source,
+            })?;
+            registry::invalidate();
         }
     }
+
+    Ok(())
 }
 
+/// The file of a template and the file it moves to under a new name within its layer, or
+/// `None` if there is no file to move. Built-in templates are never moved. Nothing is moved
+/// here, so that an editor can apply the move along with its other edits.
+pub fn template_move(
+    template: &Template,
+    new_name: &str,
+) -> std::io::Result<Option<(PathBuf, PathBuf)>> {
+    match template.root_dir() {
+        Some(dir) => template_move_in(&dir, &template.path, new_name),
+        None => Ok(None),
+    }
+}
+
+fn template_move_in(
+    dir: &Path,
+    old_name: &str,
+    new_name: &str,
+) -> std::io::Result<Option<(PathBuf, PathBuf)>> {
+    let old_path = dir.join(old_name);
+    let new_path = dir.join(new_name);
+
+    if !old_path.exists() {
+        return Ok(None);
+    }
+    if new_path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("template `{new_name}` already exists"),
+        ));
+    }
+
+    Ok(Some((old_path, new_path)))
+}
+
+/// Every template of every layer, from lowest to highest precedence, so later templates
+/// override earlier ones with the same path. Served from the registry, which only re-reads
+/// files that changed.
 pub fn get_all_templates() -> impl Iterator<Item = Template> {
-    // Built-ins first; user files can override.
-    get_built_in_templates().chain(get_user_templates())
+    let templates = registry::templates();
+
+    (0..templates.len()).map(move |i| templates[i].clone())
+}
+
+/// Resolves a template name the way a sentence would: a bare name is a verb, possibly an alias,
+/// anything with a `/` is a path. Returns every layer that has the template, the one in use
+/// first.
+pub fn which_template(name: &str) -> Vec<Template> {
+    let path = if name.contains('/') {
+        name.to_string()
+    } else {
+        find_verb_template(name).map_or_else(|| format!("verbs/{name}"), |t| t.path)
+    };
+
+    let mut templates = get_all_templates()
+        .filter(|t| t.path == path)
+        .collect::<Vec<_>>();
+    templates.reverse();
+
+    templates
+}
+
+/// Finds the system prompt template for a vocative, falling back to the shared default
+pub fn find_vocative_template(name: &str) -> Option<Template> {
+    let path = format!("vocatives/{name}");
+    let templates = get_all_templates().collect::<Vec<_>>();
+
+    let by_name = templates.iter().rev().find(|t| t.path == path);
+    let fallback = || templates.iter().rev().find(|t| t.path == "system/default");
+
+    by_name.or_else(fallback).cloned()
+}
+
+/// Finds the template a verb refers to, by name first and then by the aliases declared in
+/// front-matter. User templates take precedence over built-ins.
+pub fn find_verb_template(name: &str) -> Option<Template> {
+    let templates = get_all_templates()
+        .filter(|t| t.verb_name().is_some())
+        .collect::<Vec<_>>();
+
+    let by_name = templates.iter().rev().find(|t| t.verb_name() == Some(name));
+    let by_alias = || {
+        templates
+            .iter()
+            .rev()
+            .find(|t| t.metadata.aliases.iter().any(|alias| alias == name))
+    };
+
+    by_name.or_else(by_alias).cloned()
+}
+
+/// Why a template that is missing from the environment failed to load, if it exists at all
+pub fn template_error(path: &str) -> Option<LakonikError> {
+    registry::template_error(path)
+}
+
+pub fn build_environment() -> Result<Environment<'static>, LakonikError> {
+    build_environment_with(&TemplatePolicy::default())
+}
+
+/// Loads every template into an environment with Lakonik's filters and functions
+pub fn build_environment_with(
+    policy: &TemplatePolicy,
+) -> Result<Environment<'static>, LakonikError> {
+    let mut env = registry::environment()?;
+    functions::register(&mut env, policy);
+
+    Ok(env)
+}
+
+/// Points `LAKONIK_CONFIG` at an empty directory until it is dropped, then restores it. Tests
+/// using it, like any test that looks templates up, run `#[serial(lakonik_config)]`.
+#[cfg(test)]
+pub struct TestUserDir {
+    dir: tempfile::TempDir,
+    previous: Option<std::ffi::OsString>,
+}
+
+#[cfg(test)]
+impl TestUserDir {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let previous = std::env::var_os("LAKONIK_CONFIG");
+        unsafe {
+            std::env::set_var("LAKONIK_CONFIG", dir.path());
+        }
+
+        Self { dir, previous }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestUserDir {
+    fn drop(&mut self) {
+        unsafe {
+            match &self.previous {
+                Some(previous) => std::env::set_var("LAKONIK_CONFIG", previous),
+                None => std::env::remove_var("LAKONIK_CONFIG"),
+            }
+        }
+    }
 }
 
-pub fn build_environment() -> Environment<'static> {
-    let mut env = Environment::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serial_test::serial;
 
-    get_all_templates().for_each(|t| {
-        env.add_template_owned(t.path, t.contents)
-            .expect("Failed to add template");
-    });
+    #[test]
+    #[serial(lakonik_config)]
+    fn which_lists_every_layer_in_order_of_precedence() {
+        let user = crate::templates::TestUserDir::new();
+        let project = tempfile::tempdir().unwrap();
+        crate::config::set_project_root(Some(project.path().to_path_buf()));
+        for dir in [
+            user.path().join("verbs"),
+            project.path().join(".lakonik/templates/verbs"),
+        ] {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("create"), "{{ description }}").unwrap();
+        }
+        fs::write(user.path().join("verbs/testwhichme"), "user").unwrap();
+
+        let sources = |name| {
+            which_template(name)
+                .into_iter()
+                .map(|t| t.source)
+                .collect::<Vec<_>>()
+        };
+        let layers = (
+            sources("create"),
+            sources("testwhichme"),
+            sources("system/default"),
+        );
+        crate::config::set_project_root(None);
+
+        assert_eq!(
+            layers,
+            (
+                vec![
+                    TemplateSource::Project,
+                    TemplateSource::User,
+                    TemplateSource::BuiltIn
+                ],
+                vec![TemplateSource::User],
+                vec![TemplateSource::BuiltIn],
+            )
+        );
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn moves_stay_within_the_layer() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("verbs")).unwrap();
+        fs::write(tmp.path().join("verbs/foo"), "foo body").unwrap();
+
+        assert_eq!(
+            template_move_in(tmp.path(), "verbs/foo", "verbs/bar").unwrap(),
+            Some((tmp.path().join("verbs/foo"), tmp.path().join("verbs/bar")))
+        );
+        assert!(tmp.path().join("verbs/foo").exists());
+
+        assert_eq!(
+            template_move_in(tmp.path(), "verbs/nope", "verbs/baz").unwrap(),
+            None
+        );
+    }
 
-    env
+    #[test]
+    #[serial(lakonik_config)]
+    fn built_in_create_has_description_and_variables() {
+        let template = get_built_in_template("verbs/create").unwrap();
+        let environment = build_environment().unwrap();
+
+        assert_eq!(
+            template.description().as_deref(),
+            Some("Ask for something to be created")
+        );
+        assert_eq!(template.variables(&environment), vec!["description"]);
+    }
+
+    #[rstest]
+    #[case("verbs/create", TemplateType::Verb)]
+    #[case("parts/shell", TemplateType::Part)]
+    #[case("vocatives/qwen3", TemplateType::Vocative)]
+    #[case("system/default", TemplateType::System)]
+    #[case("layouts/review", TemplateType::Layout)]
+    #[case("README", TemplateType::Other)]
+    fn type_comes_from_directory(#[case] path: &str, #[case] expected: TemplateType) {
+        assert_eq!(TemplateType::from_path(path), expected);
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn abstract_and_non_verb_templates_are_not_invocable() {
+        assert_eq!(
+            get_built_in_template("verbs/create").unwrap().verb_name(),
+            Some("create")
+        );
+        assert_eq!(
+            get_built_in_template("verbs/base/base")
+                .unwrap()
+                .verb_name(),
+            None
+        );
+        assert_eq!(
+            get_built_in_template("parts/shell").unwrap().verb_name(),
+            None
+        );
+    }
+
+    #[test]
+    fn front_matter_is_parsed_on_load() {
+        let template = Template::new(
+            "verbs/review".to_string(),
+            "+++\naliases = [\"rv\"]\n+++\n{# Review code #}{{ description }}".to_string(),
+            TemplateSource::User,
+        );
+
+        assert_eq!(template.metadata.aliases, vec!["rv"]);
+        assert_eq!(template.body(), "{# Review code #}{{ description }}");
+        assert_eq!(template.description().as_deref(), Some("Review code"));
+
+        let broken = Template::new(
+            "verbs/broken".to_string(),
+            "+++\naliases = 42\n+++\nbody".to_string(),
+            TemplateSource::User,
+        );
+        assert!(broken.metadata_error.is_some());
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn project_dir_is_found_in_parents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let templates = tmp.path().join(PROJECT_TEMPLATE_DIR);
+        let nested = tmp.path().join("src/deeply/nested");
+        fs::create_dir_all(&templates).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(find_project_template_dir(&nested), Some(templates));
+    }
+
+    #[test]
+    #[serial(lakonik_config)]
+    fn rename_refuses_to_overwrite() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("verbs")).unwrap();
+        fs::write(tmp.path().join("verbs/foo"), "foo").unwrap();
+        fs::write(tmp.path().join("verbs/bar"), "bar").unwrap();
+
+        let err = template_move_in(tmp.path(), "verbs/foo", "verbs/bar").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
 }
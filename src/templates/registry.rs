@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use minijinja::Environment;
+use walkdir::WalkDir;
+
+use super::fixtures::is_fixture;
+use super::{
+    Template, TemplateSource, get_built_in_templates, project_template_dir, user_template_dir,
+};
+use crate::error::LakonikError;
+
+/// A template along with the state of the file it was read from
+#[derive(Debug)]
+struct CachedFile {
+    modified: Option<SystemTime>,
+    len: u64,
+    template: Template,
+}
+
+/// The templates of one directory. Refreshing it only stats the files, and only reads the ones
+/// whose modification time or size changed since they were loaded.
+#[derive(Debug, Default)]
+struct LayerCache {
+    root: Option<PathBuf>,
+    files: BTreeMap<String, CachedFile>,
+}
+
+impl LayerCache {
+    /// Catches up with the files under `root`, returning whether any template changed
+    fn refresh(&mut self, root: Option<PathBuf>, source: TemplateSource) -> bool {
+        let mut changed = false;
+        if root != self.root {
+            changed = !self.files.is_empty();
+            self.files.clear();
+            self.root.clone_from(&root);
+        }
+        let Some(root) = root else {
+            return changed;
+        };
+
+        let mut seen = BTreeSet::new();
+        for entry in WalkDir::new(&root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+        {
+            let Some(path) = relative_path(&root, entry.path()).filter(|p| !is_fixture(p)) else {
+                continue;
+            };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let modified = metadata.modified().ok();
+            seen.insert(path.clone());
+
+            let unchanged = self
+                .files
+                .get(&path)
+                .is_some_and(|f| f.modified == modified && f.len == metadata.len());
+            if unchanged {
+                continue;
+            }
+
+            changed = true;
+            match fs::read_to_string(entry.path()) {
+                Ok(contents) => {
+                    let template = Template::new(path.clone(), contents, source);
+                    self.files.insert(
+                        path,
+                        CachedFile {
+                            modified,
+                            len: metadata.len(),
+                            template,
+                        },
+                    );
+                }
+                Err(_) => {
+                    self.files.remove(&path);
+                }
+            }
+        }
+
+        let loaded = self.files.len();
+        self.files.retain(|path, _| seen.contains(path));
+
+        changed || self.files.len() != loaded
+    }
+
+    fn templates(&self) -> impl Iterator<Item = &Template> {
+        self.files.values().map(|f| &f.template)
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+
+    Some(rel.to_string_lossy().replace('\\', "/"))
+}
+
+/// How long the template directories are taken to be unchanged after they were checked. The
+/// language server looks templates up several times per keystroke, which should not walk both
+/// directories each time. Tests use templates right after writing them.
+const REFRESH_INTERVAL: Duration = if cfg!(test) {
+    Duration::ZERO
+} else {
+    Duration::from_millis(500)
+};
+
+struct Registry {
+    built_in: Vec<Template>,
+    user: LayerCache,
+    project: LayerCache,
+    /// When the layers were last caught up with their directories
+    refreshed_at: Option<Instant>,
+    /// Every template from lowest to highest precedence, rebuilt whenever a layer changes
+    templates: Arc<Vec<Template>>,
+    /// Environment with every template added, built on first use after a change
+    environment: Option<Environment<'static>>,
+    /// Templates left out of `environment` because they don't load, reported once they are used
+    broken: BTreeSet<String>,
+}
+
+impl Registry {
+    fn new(built_in: Vec<Template>) -> Self {
+        Registry {
+            templates: Arc::new(built_in.clone()),
+            built_in,
+            user: LayerCache::default(),
+            project: LayerCache::default(),
+            refreshed_at: None,
+            environment: None,
+            broken: BTreeSet::new(),
+        }
+    }
+
+    /// Catches up with the template directories, unless they were checked less than `interval`
+    /// ago. Directories that moved, such as a new `LAKONIK_CONFIG`, are always read.
+    fn refresh(
+        &mut self,
+        user: Option<PathBuf>,
+        project: Option<PathBuf>,
+        now: Instant,
+        interval: Duration,
+    ) {
+        let recent = self
+            .refreshed_at
+            .is_some_and(|at| now.saturating_duration_since(at) < interval);
+        if recent && self.user.root == user && self.project.root == project {
+            return;
+        }
+        self.refreshed_at = Some(now);
+
+        // Both layers have to be refreshed, so `|` rather than `||`
+        let changed = self.user.refresh(user, TemplateSource::User)
+            | self.project.refresh(project, TemplateSource::Project);
+        if changed {
+            self.templates = Arc::new(
+                self.built_in
+                    .iter()
+                    .chain(self.user.templates())
+                    .chain(self.project.templates())
+                    .cloned()
+                    .collect(),
+            );
+            self.environment = None;
+        }
+    }
+}
+
+static REGISTRY: LazyLock<Mutex<Registry>> =
+    LazyLock::new(|| Mutex::new(Registry::new(get_built_in_templates().collect())));
+
+/// The registry, caught up with the user and project template directories
+fn refreshed() -> MutexGuard<'static, Registry> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.refresh(
+        user_template_dir(),
+        project_template_dir(),
+        Instant::now(),
+        REFRESH_INTERVAL,
+    );
+
+    registry
+}
+
+/// Makes the next lookup read the template directories, after Lakonik itself changed them
+pub fn invalidate() {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .refreshed_at = None;
+}
+
+/// Every template of every layer, from lowest to highest precedence
+pub fn templates() -> Arc<Vec<Template>> {
+    refreshed().templates.clone()
+}
+
+/// Adds a template, failing if its front-matter or its body is invalid
+fn add_template(environment: &mut Environment<'static>, t: &Template) -> Result<(), LakonikError> {
+    if let Some(err) = &t.metadata_error {
+        return Err(LakonikError::Config {
+            message: format!("invalid front-matter in template `{}`: {err}", t.path),
+        });
+    }
+
+    environment
+        .add_template_owned(t.path.clone(), t.body().to_string())
+        .map_err(|source| LakonikError::Render {
+            template: t.path.clone(),
+            source,
+            span: None,
+        })
+}
+
+/// An environment with every template added, but without Lakonik's filters and functions.
+/// Templates that don't load are left out, so that one broken template only fails the sentences
+/// that use it.
+pub fn environment() -> Result<Environment<'static>, LakonikError> {
+    let mut registry = refreshed();
+    if let Some(environment) = &registry.environment {
+        return Ok(environment.clone());
+    }
+
+    let mut environment = Environment::new();
+    let mut broken = BTreeSet::new();
+    for t in registry.templates.iter() {
+        if add_template(&mut environment, t).is_err() {
+            // The templates it overrides are removed too, rather than used in its place
+            environment.remove_template(&t.path);
+            broken.insert(t.path.clone());
+        } else {
+            broken.remove(&t.path);
+        }
+    }
+    registry.environment = Some(environment.clone());
+    registry.broken = broken;
+
+    Ok(environment)
+}
+
+/// Why a template was left out of the environment, if it was
+pub fn template_error(path: &str) -> Option<LakonikError> {
+    let registry = refreshed();
+    if !registry.broken.contains(path) {
+        return None;
+    }
+    let template = registry.templates.iter().rev().find(|t| t.path == path)?;
+
+    add_template(&mut Environment::new(), template).err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(cache: &LayerCache) -> Vec<(&str, &str)> {
+        cache
+            .templates()
+            .map(|t| (t.path.as_str(), t.contents.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn layer_reloads_only_when_files_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = Some(dir.path().to_path_buf());
+        fs::create_dir_all(dir.path().join("verbs")).unwrap();
+        fs::write(dir.path().join("verbs/foo"), "foo").unwrap();
+        fs::write(dir.path().join("verbs/foo.test.toml"), "").unwrap();
+        let mut cache = LayerCache::default();
+
+        assert!(cache.refresh(root.clone(), TemplateSource::User));
+        assert_eq!(paths(&cache), vec![("verbs/foo", "foo")]);
+        assert!(!cache.refresh(root.clone(), TemplateSource::User));
+
+        fs::write(dir.path().join("verbs/foo"), "foo, longer").unwrap();
+        fs::write(dir.path().join("verbs/bar"), "bar").unwrap();
+        assert!(cache.refresh(root.clone(), TemplateSource::User));
+        assert_eq!(
+            paths(&cache),
+            vec![("verbs/bar", "bar"), ("verbs/foo", "foo, longer")]
+        );
+
+        fs::remove_file(dir.path().join("verbs/bar")).unwrap();
+        assert!(cache.refresh(root, TemplateSource::User));
+        assert_eq!(paths(&cache), vec![("verbs/foo", "foo, longer")]);
+    }
+
+    #[test]
+    fn layer_is_replaced_when_its_root_moves() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::write(first.path().join("a"), "a").unwrap();
+        fs::write(second.path().join("b"), "b").unwrap();
+        let mut cache = LayerCache::default();
+
+        cache.refresh(Some(first.path().to_path_buf()), TemplateSource::User);
+        assert!(cache.refresh(Some(second.path().to_path_buf()), TemplateSource::User));
+        assert_eq!(paths(&cache), vec![("b", "b")]);
+        assert!(cache.refresh(None, TemplateSource::User));
+        assert!(paths(&cache).is_empty());
+    }
+
+    #[test]
+    fn directories_are_only_checked_again_after_the_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = Some(dir.path().to_path_buf());
+        let interval = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut registry = Registry::new(Vec::new());
+
+        registry.refresh(root.clone(), None, start, interval);
+        fs::write(dir.path().join("foo"), "foo").unwrap();
+        registry.refresh(root.clone(), None, start + interval / 2, interval);
+        assert!(registry.templates.is_empty());
+
+        registry.refresh(root.clone(), None, start + interval, interval);
+        assert_eq!(registry.templates.len(), 1);
+
+        let moved = tempfile::tempdir().unwrap();
+        registry.refresh(
+            Some(moved.path().to_path_buf()),
+            None,
+            start + interval,
+            interval,
+        );
+        assert!(registry.templates.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// An empty directory for `lakonik` to run in, with configuration and templates of its own
+fn workspace(templates: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, contents) in templates {
+        let path = dir.path().join("config").join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    dir
+}
+
+fn lakonik_in(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lakonik"))
+        .args(args)
+        .current_dir(dir)
+        .env("LAKONIK_CONFIG", dir.join("config"))
+        .env("LAKONIK_CONFIG_FILE", dir.join("config.toml"))
+        .env_remove("OPENAI_API_KEY")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap()
+}
+
+fn lakonik(args: &[&str]) -> Output {
+    lakonik_in(workspace(&[]).path(), args, &[])
+}
+
+#[test]
+fn eval_resolves_vocative_aliases() {
+    let output = lakonik(&["eval", "--no-persist", "q3", "create", "foo"]);
+    assert!(output.status.success(), "{output:?}");
+
+    let result: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["vocative"], "qwen3");
+    assert_eq!(result["verb"], "create");
+}
+
+#[test]
+fn failures_exit_with_the_code_of_their_error() {
+    let dir = workspace(&[
+        ("verbs/broken", "{% if %}"),
+        ("verbs/review", "review {{ description }}"),
+        (
+            "verbs/review.test.toml",
+            "[[case]]\ninput = \"qwen3 review x\"\nexpected = \"something else\"\n",
+        ),
+    ]);
+    let exit_code = |args: &[&str], env: &[(&str, &str)]| {
+        let output = lakonik_in(dir.path(), args, env);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("error: "),
+            "{output:?}"
+        );
+        output.status.code()
+    };
+
+    assert_eq!(exit_code(&["eval", "qwen3", "broken", "x"], &[]), Some(70));
+    assert_eq!(exit_code(&["templates", "check"], &[]), Some(65));
+    assert_eq!(exit_code(&["templates", "test"], &[]), Some(65));
+    assert_eq!(exit_code(&["config", "get", "default_verb"], &[]), Some(78));
+    assert_eq!(
+        exit_code(&["config", "get", "providers.openai.api_key"], &[]),
+        Some(78)
+    );
+    assert_eq!(
+        exit_code(&["templates", "edit", "review"], &[("VISUAL", "false")]),
+        Some(69)
+    );
+}